@@ -0,0 +1,13 @@
+-- Add attachments table, one row per file received with a message
+CREATE TABLE IF NOT EXISTS attachments (
+    id TEXT PRIMARY KEY,
+    message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    signal_id TEXT,
+    content_type TEXT NOT NULL,
+    size BIGINT,
+    filename TEXT,
+    path TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments (message_id);
@@ -0,0 +1,14 @@
+-- Remember the Signal timestamp of each message so reactions can find it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS signal_timestamp BIGINT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_signal_timestamp ON messages (signal_timestamp);
+
+-- Add feedback table, one emoji reaction per person per answer
+CREATE TABLE IF NOT EXISTS feedback (
+    id TEXT PRIMARY KEY,
+    message_id TEXT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    reactor TEXT NOT NULL,
+    emoji TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (message_id, reactor)
+);
@@ -0,0 +1,11 @@
+-- Who sent each message and where, so edits and deletions from Signal can
+-- find the stored row and bot replies can be deleted remotely later
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS sender TEXT;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS group_id TEXT;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS reply_to TEXT REFERENCES messages(id) ON DELETE SET NULL;
+
+-- Edits update content in place; deletions blank it and leave a tombstone
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS edited_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_messages_reply_to ON messages (reply_to);
@@ -0,0 +1,6 @@
+-- Long replies go out as several Signal messages. signal_timestamp holds the
+-- first part's sent timestamp; the rest are kept here so reactions to and
+-- deletions of any part find the stored reply
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS part_timestamps BIGINT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_messages_part_timestamps ON messages USING GIN (part_timestamps);
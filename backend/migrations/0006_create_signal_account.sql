@@ -0,0 +1,8 @@
+-- The Signal account the bot runs as. A single row: the active number, plus
+-- a number that has been sent a verification code but not yet verified
+CREATE TABLE IF NOT EXISTS signal_account (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    phone_number TEXT,
+    pending_number TEXT,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,13 @@
+-- Who may talk to the bot. Senders without a row fall under the configured
+-- policy for unknown senders; 'pending' rows are waiting for an admin to
+-- allow or block them
+CREATE TABLE IF NOT EXISTS contacts (
+    sender TEXT PRIMARY KEY,
+    status TEXT NOT NULL CHECK (status IN ('allowed', 'blocked', 'pending')),
+    name TEXT,
+    first_message TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_contacts_status ON contacts (status);
@@ -0,0 +1,16 @@
+-- Invite codes that let new staffers grant themselves access by texting
+-- `/join CODE`. Codes expire, can be limited to a number of uses, and can be
+-- revoked early
+CREATE TABLE IF NOT EXISTS invites (
+    code TEXT PRIMARY KEY,
+    created_by TEXT,
+    max_uses INTEGER CHECK (max_uses > 0),
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Contacts who joined with an invite remember which one, and who issued it
+ALTER TABLE contacts ADD COLUMN IF NOT EXISTS invite_code TEXT REFERENCES invites(code) ON DELETE SET NULL;
+ALTER TABLE contacts ADD COLUMN IF NOT EXISTS invited_by TEXT;
@@ -0,0 +1,17 @@
+-- Contacts whose identity key (safety number) changed, e.g. after
+-- reinstalling Signal. Sends to them fail until the new key is trusted,
+-- automatically or by an admin depending on the trust policy
+CREATE TABLE IF NOT EXISTS identity_changes (
+    id TEXT PRIMARY KEY,
+    number TEXT NOT NULL,
+    -- 'receive' or 'send', whichever noticed the change first
+    source TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('pending', 'trusted', 'refused')),
+    error TEXT,
+    detected_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    resolved_at TIMESTAMP WITH TIME ZONE
+);
+
+-- One open change per contact; repeats while it is pending are the same one
+CREATE UNIQUE INDEX IF NOT EXISTS idx_identity_changes_pending
+    ON identity_changes (number) WHERE status = 'pending';
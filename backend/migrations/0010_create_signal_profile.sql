@@ -0,0 +1,10 @@
+-- The profile the bot's Signal account shows. A single row, kept so it can be
+-- applied again whenever the bot registers a number
+CREATE TABLE IF NOT EXISTS signal_profile (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    name TEXT NOT NULL,
+    about TEXT,
+    about_emoji TEXT,
+    avatar BYTEA,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,14 @@
+-- Conversations with disappearing messages on: stored messages are deleted
+-- once expires_at passes, and each conversation's timer is remembered so
+-- replies can be sent with it
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages (expires_at)
+    WHERE expires_at IS NOT NULL;
+
+-- Keyed by group id for groups and by the other person for direct chats
+CREATE TABLE IF NOT EXISTS conversation_timers (
+    conversation TEXT PRIMARY KEY,
+    expires_in_seconds INTEGER NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
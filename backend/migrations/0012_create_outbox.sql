@@ -0,0 +1,25 @@
+-- Outgoing Signal messages, kept until they are delivered. Failed sends are
+-- retried with a growing delay and end up 'dead' after too many attempts,
+-- where an admin can look at them and retry
+CREATE TABLE IF NOT EXISTS outbox (
+    id TEXT PRIMARY KEY,
+    -- Number or ACI uuid, or the group id when is_group is set
+    recipient TEXT NOT NULL,
+    is_group BOOLEAN NOT NULL DEFAULT FALSE,
+    content TEXT NOT NULL,
+    quote_timestamp BIGINT,
+    quote_author TEXT,
+    quote_text TEXT,
+    -- The stored reply this delivers; a reply that disappears before it is
+    -- delivered is never sent
+    message_id TEXT REFERENCES messages(id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'dead')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    sent_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_due ON outbox (next_attempt_at)
+    WHERE status = 'pending';
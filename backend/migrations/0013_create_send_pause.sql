@@ -0,0 +1,10 @@
+-- Set while Signal is rate limiting the bot's account. Nothing is sent until
+-- paused_until passes, or, when Signal asked for a captcha and gave no time,
+-- until an operator solves the challenge
+CREATE TABLE IF NOT EXISTS signal_send_pause (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    paused_until TIMESTAMP WITH TIME ZONE,
+    challenge TEXT,
+    reason TEXT NOT NULL,
+    detected_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+-- Replies split into several Signal messages can fail partway through.
+-- Parts already delivered are remembered, with the timestamps Signal gave
+-- them, so the next attempt carries on from the first part not yet sent
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS parts_sent INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS sent_timestamps BIGINT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,257 @@
+//! Who the bot answers: a sender allowlist and blocklist
+//!
+//! Each sender can have a contacts entry marking them allowed or blocked.
+//! Anyone else is an unknown sender and is handled by
+//! [`BotConfig::unknown_senders`]: answered, ignored, sent a fixed reply, or
+//! queued as a pending contact for an admin to approve. Entries are managed
+//! through the `/contacts` endpoints, or created by redeeming an invite (see
+//! [`crate::invites`]).
+
+use crate::config::{BotConfig, UnknownSenderPolicy};
+use crate::error::{AppError, AppResult};
+use crate::signal::{SignalAddress, SignalMessage};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::info;
+
+/// Where a sender stands with the bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ContactStatus {
+    /// Always answered
+    Allowed,
+    /// Never answered
+    Blocked,
+    /// An unknown sender waiting for an admin to allow or block them
+    Pending,
+}
+
+/// A sender's contacts entry
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Contact {
+    /// Phone number or ACI uuid the sender's messages come from
+    pub sender: String,
+    pub status: ContactStatus,
+    /// Who this is, for the admin's benefit
+    pub name: Option<String>,
+    /// The message that queued a pending sender
+    pub first_message: Option<String>,
+    /// Invite code the sender joined with, if any
+    pub invite_code: Option<String>,
+    /// Who issued that invite
+    pub invited_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What to do with an incoming message, decided by its sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Handle the message as usual
+    Answer,
+    /// Drop the message
+    Ignore,
+    /// Send the configured reply for unknown senders instead of an answer
+    Refuse,
+}
+
+/// Decide how to treat `message`, by its sender
+///
+/// The sender's entry may be under their uuid or their number; if both
+/// exist and one of them is blocked, the sender is blocked. Under
+/// [`UnknownSenderPolicy::Approve`] an unknown sender who messages the bot
+/// directly is added as a pending contact, keeping their profile name and
+/// message so the admin can see who is asking.
+pub async fn check(
+    pool: &PgPool,
+    config: &BotConfig,
+    message: &SignalMessage,
+) -> anyhow::Result<Access> {
+    let status: Option<ContactStatus> = sqlx::query_scalar(
+        "SELECT status FROM contacts WHERE sender = ANY($1) \
+         ORDER BY status = 'blocked' DESC, status = 'allowed' DESC LIMIT 1",
+    )
+    .bind(message.sender_ids())
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(match status {
+        Some(ContactStatus::Allowed) => Access::Answer,
+        Some(ContactStatus::Blocked | ContactStatus::Pending) => Access::Ignore,
+        None => match config.unknown_senders {
+            UnknownSenderPolicy::Allow => Access::Answer,
+            UnknownSenderPolicy::Ignore => Access::Ignore,
+            UnknownSenderPolicy::Reply => Access::Refuse,
+            // Only a message written to the bot asks for approval; group
+            // chatter in particular stays out of the contact list
+            UnknownSenderPolicy::Approve if !message.is_new_direct_message() => Access::Ignore,
+            UnknownSenderPolicy::Approve => {
+                sqlx::query(
+                    "INSERT INTO contacts (sender, status, name, first_message) \
+                     VALUES ($1, 'pending', $2, $3) ON CONFLICT (sender) DO NOTHING",
+                )
+                .bind(&message.from)
+                .bind(&message.source.name)
+                .bind(&message.content)
+                .execute(pool)
+                .await?;
+                info!("📋 {} is waiting for approval", message.from);
+                Access::Ignore
+            }
+        },
+    })
+}
+
+/// Query parameters for listing contacts
+#[derive(Debug, Deserialize)]
+pub struct ContactsQuery {
+    /// Only list contacts with this status
+    pub status: Option<ContactStatus>,
+}
+
+/// Request to allow or block a sender
+#[derive(Debug, Deserialize)]
+pub struct UpdateContact {
+    pub status: ContactStatus,
+    /// Who this is; keeps the existing name when left out
+    pub name: Option<String>,
+}
+
+/// Senders are known by ACI uuid or phone number; usernames are not stable
+/// enough to key on
+fn validate_sender(sender: &str) -> AppResult<()> {
+    let address = SignalAddress::parse(sender);
+    if address.is_none_or(|address| address.username.is_some()) {
+        return Err(AppError::validation(
+            "sender",
+            "Sender must be a phone number with country code or a Signal uuid",
+        ));
+    }
+    Ok(())
+}
+
+/// List contacts, most recently changed first
+///
+/// # Example
+///
+/// ```json
+/// GET /contacts?status=pending
+///
+/// Response:
+/// [
+///   {
+///     "sender": "+15550001111",
+///     "status": "pending",
+///     "name": null,
+///     "first_message": "Hi, is this the prep line?",
+///     "invite_code": null,
+///     "invited_by": null,
+///     "created_at": "2025-01-01T12:00:00Z",
+///     "updated_at": "2025-01-01T12:00:00Z"
+///   }
+/// ]
+/// ```
+pub async fn list_contacts(
+    State(state): State<AppState>,
+    Query(query): Query<ContactsQuery>,
+) -> AppResult<Json<Vec<Contact>>> {
+    let contacts = sqlx::query_as(
+        "SELECT * FROM contacts WHERE $1::TEXT IS NULL OR status = $1 \
+         ORDER BY updated_at DESC, sender",
+    )
+    .bind(query.status)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(contacts))
+}
+
+/// Allow or block a sender, approving or rejecting them if they were pending
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the sender is malformed or the status is
+///   `pending`, which only unknown senders get
+/// * `AppError::Database` - If the contact cannot be stored
+///
+/// # Example
+///
+/// ```json
+/// PUT /contacts/+15550001111
+/// {
+///   "status": "allowed",
+///   "name": "Mitch Bradley"
+/// }
+///
+/// Response:
+/// {
+///   "sender": "+15550001111",
+///   "status": "allowed",
+///   "name": "Mitch Bradley",
+///   "first_message": null,
+///   "invite_code": null,
+///   "invited_by": null,
+///   "created_at": "2025-01-01T12:00:00Z",
+///   "updated_at": "2025-01-01T12:00:00Z"
+/// }
+/// ```
+pub async fn update_contact(
+    State(state): State<AppState>,
+    Path(sender): Path<String>,
+    Json(payload): Json<UpdateContact>,
+) -> AppResult<Json<Contact>> {
+    validate_sender(&sender)?;
+    if payload.status == ContactStatus::Pending {
+        return Err(AppError::validation(
+            "status",
+            "Status must be 'allowed' or 'blocked'",
+        ));
+    }
+
+    let contact: Contact = sqlx::query_as(
+        "INSERT INTO contacts (sender, status, name) VALUES ($1, $2, $3) \
+         ON CONFLICT (sender) DO UPDATE SET status = EXCLUDED.status, \
+         name = COALESCE(EXCLUDED.name, contacts.name), updated_at = NOW() \
+         RETURNING *",
+    )
+    .bind(&sender)
+    .bind(payload.status)
+    .bind(&payload.name)
+    .fetch_one(&state.pool)
+    .await?;
+    info!("📋 {} is now {:?}", sender, contact.status);
+
+    Ok(Json(contact))
+}
+
+/// Remove a sender's entry, making them an unknown sender again
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If the sender has no entry
+///
+/// # Example
+///
+/// ```json
+/// DELETE /contacts/+15550001111
+///
+/// Response: the removed contact
+/// ```
+pub async fn delete_contact(
+    State(state): State<AppState>,
+    Path(sender): Path<String>,
+) -> AppResult<Json<Contact>> {
+    let contact: Option<Contact> =
+        sqlx::query_as("DELETE FROM contacts WHERE sender = $1 RETURNING *")
+            .bind(&sender)
+            .fetch_optional(&state.pool)
+            .await?;
+    let contact = contact.ok_or_else(|| AppError::not_found(format!("contact {sender}")))?;
+    info!("📋 Removed {} from contacts", sender);
+
+    Ok(Json(contact))
+}
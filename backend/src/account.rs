@@ -0,0 +1,896 @@
+//! The Signal account the bot runs as
+//!
+//! The active number lives in the database so it can be changed from the web
+//! UI: `POST /api/signal/update-number` asks Signal for a verification code
+//! for a new number, `POST /api/signal/verify` completes the registration and
+//! switches the live client over, and `GET /api/signal/status` reports where
+//! things stand. Registration drives `signal-cli register` and `verify`
+//! through a [`Registrar`]: a local signal-cli for the `cli` and `jsonrpc`
+//! transports, or the signal-cli-rest-api container for `rest`. signald and
+//! the simulator keep their own account, so these endpoints are turned away
+//! with them.
+//!
+//! Instead of registering a number of its own, the bot can also be linked to
+//! an existing account as a secondary device: `POST /api/signal/link` returns
+//! a provisioning URI and QR code to scan from the phone, and
+//! `GET /api/signal/link` reports when linking has finished.
+
+use crate::error::{AppError, AppResult};
+use crate::profile;
+use crate::signal::{rest_error_message, SignalClient, SwitchableSignalClient};
+use crate::AppState;
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::Json;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// How long signal-cli gets to print the provisioning URI
+const LINK_URI_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the REST API is asked whether the phone has linked
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the REST API's provisioning URI stays scannable
+const LINK_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Device name shown in the phone's list of linked devices
+const DEFAULT_DEVICE_NAME: &str = "Senator Budd Bot";
+
+/// Options for requesting a verification code
+#[derive(Debug, Clone, Default)]
+pub struct RegisterOptions {
+    /// Token from https://signalcaptchas.org, required once Signal asks for one
+    pub captcha: Option<String>,
+    /// Deliver the code by voice call instead of SMS
+    pub voice: bool,
+}
+
+/// Registers numbers with Signal
+#[async_trait]
+pub trait Registrar: Send + Sync + 'static {
+    /// Ask Signal to send a verification code to `number`
+    async fn register(&self, number: &str, options: &RegisterOptions) -> anyhow::Result<()>;
+
+    /// Complete registration of `number` with the code Signal sent
+    ///
+    /// `pin` is the registration lock PIN, if the number has one.
+    async fn verify(&self, number: &str, code: &str, pin: Option<&str>) -> anyhow::Result<()>;
+
+    /// Whether `number` is registered and usable on this machine
+    async fn is_registered(&self, number: &str) -> anyhow::Result<bool>;
+
+    /// Start linking as a secondary device named `device_name`
+    async fn link(&self, device_name: &str) -> anyhow::Result<PendingLink>;
+}
+
+/// A device link waiting for the phone to scan its QR code
+pub struct PendingLink {
+    /// `sgnl://linkdevice?...` provisioning URI to show as a QR code
+    pub uri: String,
+    /// Resolves to the linked account's number once the phone confirms
+    pub linked: BoxFuture<'static, anyhow::Result<String>>,
+}
+
+/// Registers numbers by running signal-cli
+pub struct SignalCliRegistrar {
+    program: String,
+    args: Vec<String>,
+}
+
+impl Default for SignalCliRegistrar {
+    fn default() -> Self {
+        Self::with_command("signal-cli", Vec::new())
+    }
+}
+
+impl SignalCliRegistrar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run an arbitrary signal-cli compatible command, `args` first
+    ///
+    /// Mainly useful for tests, which substitute a fake signal-cli script.
+    pub fn with_command(program: impl Into<String>, args: Vec<String>) -> Self {
+        Self {
+            program: program.into(),
+            args,
+        }
+    }
+
+    /// Run signal-cli with `args`, returning its stdout
+    async fn run(&self, args: &[&str]) -> anyhow::Result<String> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .args(args)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let command = args
+                .iter()
+                .find(|a| !a.starts_with(['-', '+']))
+                .unwrap_or(&"");
+            error!(
+                "❌ signal-cli {} failed with status: {}",
+                command, output.status
+            );
+            error!("📋 stderr: {}", stderr);
+            anyhow::bail!("signal-cli {} failed: {}", command, stderr.trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[async_trait]
+impl Registrar for SignalCliRegistrar {
+    async fn register(&self, number: &str, options: &RegisterOptions) -> anyhow::Result<()> {
+        let mut args = vec!["-a", number, "register"];
+        if options.voice {
+            args.push("--voice");
+        }
+        if let Some(captcha) = &options.captcha {
+            args.extend(["--captcha", captcha.as_str()]);
+        }
+        self.run(&args).await.map(|_| ())
+    }
+
+    async fn verify(&self, number: &str, code: &str, pin: Option<&str>) -> anyhow::Result<()> {
+        let mut args = vec!["-a", number, "verify", code];
+        if let Some(pin) = pin {
+            args.extend(["--pin", pin]);
+        }
+        self.run(&args).await.map(|_| ())
+    }
+
+    async fn is_registered(&self, number: &str) -> anyhow::Result<bool> {
+        // Lists the accounts registered locally, e.g. `Number: +15550000000`
+        let accounts = self.run(&["listAccounts"]).await?;
+        Ok(accounts
+            .lines()
+            .any(|line| line.split_whitespace().any(|word| word == number)))
+    }
+
+    async fn link(&self, device_name: &str) -> anyhow::Result<PendingLink> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .args(["link", "-n", device_name])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("signal-cli link has no stdout")?;
+        let mut lines = BufReader::new(stdout).lines();
+
+        // The URI comes first; signal-cli then waits for the phone to scan it
+        let uri = tokio::time::timeout(LINK_URI_TIMEOUT, async {
+            while let Some(line) = lines.next_line().await? {
+                let line = line.trim();
+                if line.starts_with("sgnl://") || line.starts_with("tsdevice:") {
+                    return Ok(Some(line.to_string()));
+                }
+            }
+            anyhow::Ok(None)
+        })
+        .await
+        .context("timed out waiting for signal-cli link")??;
+        let Some(uri) = uri else {
+            let output = child.wait_with_output().await?;
+            anyhow::bail!(
+                "signal-cli link failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        };
+
+        let linked = async move {
+            // Prints e.g. `Associated with: +15550000000` once the phone confirms
+            let mut number = None;
+            while let Some(line) = lines.next_line().await? {
+                if let Some(associated) = line.trim().strip_prefix("Associated with:") {
+                    number = Some(associated.trim().to_string());
+                }
+            }
+            let output = child.wait_with_output().await?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "signal-cli link failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            number.context("signal-cli link finished without reporting the linked number")
+        };
+
+        Ok(PendingLink {
+            uri,
+            linked: linked.boxed(),
+        })
+    }
+}
+
+/// Registers numbers through a signal-cli-rest-api container
+///
+/// The container runs signal-cli itself, so registering and linking happen
+/// there rather than in this image.
+#[derive(Clone)]
+pub struct SignalRestRegistrar {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct RestRegisterRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captcha: Option<&'a str>,
+    use_voice: bool,
+}
+
+#[derive(Serialize)]
+struct RestVerifyRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pin: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct RestLinkResponse {
+    device_link_uri: String,
+}
+
+impl SignalRestRegistrar {
+    /// Register through the REST API at `base_url`, e.g. `http://signal-api:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Numbers the container has registered or linked
+    async fn accounts(&self) -> anyhow::Result<Vec<String>> {
+        let resp = self
+            .http
+            .get(format!("{}/v1/accounts", self.base_url))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api accounts failed: {}",
+                rest_error_message(resp).await
+            );
+        }
+        Ok(resp.json().await?)
+    }
+}
+
+#[async_trait]
+impl Registrar for SignalRestRegistrar {
+    async fn register(&self, number: &str, options: &RegisterOptions) -> anyhow::Result<()> {
+        let resp = self
+            .http
+            .post(format!("{}/v1/register/{}", self.base_url, number))
+            .json(&RestRegisterRequest {
+                captcha: options.captcha.as_deref(),
+                use_voice: options.voice,
+            })
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api register failed: {}",
+                rest_error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn verify(&self, number: &str, code: &str, pin: Option<&str>) -> anyhow::Result<()> {
+        let resp = self
+            .http
+            .post(format!(
+                "{}/v1/register/{}/verify/{}",
+                self.base_url, number, code
+            ))
+            .json(&RestVerifyRequest { pin })
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api verify failed: {}",
+                rest_error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn is_registered(&self, number: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .accounts()
+            .await?
+            .iter()
+            .any(|account| account == number))
+    }
+
+    async fn link(&self, device_name: &str) -> anyhow::Result<PendingLink> {
+        // The account the phone links shows up as a new one in the container
+        let before = self.accounts().await?;
+        let resp = self
+            .http
+            .get(format!("{}/v1/qrcodelink/raw", self.base_url))
+            .query(&[("device_name", device_name)])
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api link failed: {}",
+                rest_error_message(resp).await
+            );
+        }
+        let uri = resp.json::<RestLinkResponse>().await?.device_link_uri;
+
+        let registrar = self.clone();
+        let linked = async move {
+            tokio::time::timeout(LINK_TIMEOUT, async {
+                loop {
+                    tokio::time::sleep(LINK_POLL_INTERVAL).await;
+                    let accounts = registrar.accounts().await?;
+                    if let Some(number) = accounts.into_iter().find(|a| !before.contains(a)) {
+                        return Ok(number);
+                    }
+                }
+            })
+            .await
+            .context("the phone did not link before the QR code expired")?
+        };
+
+        Ok(PendingLink {
+            uri,
+            linked: linked.boxed(),
+        })
+    }
+}
+
+/// Creates a Signal client for a number, using the configured transport
+pub type Connector = Box<dyn Fn(&str) -> Arc<dyn SignalClient> + Send + Sync>;
+
+/// Everything needed to change the number the bot runs as
+pub struct SignalAccount {
+    /// `None` when the transport keeps its own account, as signald does
+    registrar: Option<Arc<dyn Registrar>>,
+    client: Arc<SwitchableSignalClient>,
+    connect: Connector,
+    /// Progress of the latest device link
+    link: Mutex<LinkStatus>,
+}
+
+impl SignalAccount {
+    /// `client` must be the same client the app state sends through
+    pub fn new(
+        registrar: Arc<dyn Registrar>,
+        client: Arc<SwitchableSignalClient>,
+        connect: Connector,
+    ) -> Self {
+        Self {
+            registrar: Some(registrar),
+            client,
+            connect,
+            link: Mutex::new(LinkStatus::default()),
+        }
+    }
+
+    /// For transports that keep their own account, like signald and the
+    /// simulator: the number can be reported but not registered or linked
+    pub fn without_registrar(client: Arc<SwitchableSignalClient>, connect: Connector) -> Self {
+        Self {
+            registrar: None,
+            client,
+            connect,
+            link: Mutex::new(LinkStatus::default()),
+        }
+    }
+
+    fn registrar(&self) -> AppResult<&dyn Registrar> {
+        self.registrar.as_deref().ok_or_else(|| {
+            AppError::config(
+                "Registering and linking numbers is only supported with SIGNAL_TRANSPORT \
+                 cli, jsonrpc or rest; set up the account in the transport itself",
+            )
+        })
+    }
+
+    /// Send and receive as `number` from now on
+    pub fn switch_to(&self, number: &str) {
+        self.client.replace((self.connect)(number));
+        info!("📱 Signal client switched to {}", number);
+    }
+}
+
+/// The active and pending numbers on record
+#[derive(Debug, Default, sqlx::FromRow)]
+pub struct StoredAccount {
+    pub phone_number: Option<String>,
+    pub pending_number: Option<String>,
+}
+
+/// Look up the stored account, if any
+pub async fn stored_account(pool: &PgPool) -> anyhow::Result<StoredAccount> {
+    let account =
+        sqlx::query_as("SELECT phone_number, pending_number FROM signal_account WHERE id")
+            .fetch_optional(pool)
+            .await?;
+    Ok(account.unwrap_or_default())
+}
+
+/// The number the bot currently runs as
+pub async fn active_number(pool: &PgPool) -> anyhow::Result<Option<String>> {
+    Ok(stored_account(pool).await?.phone_number)
+}
+
+/// Record `number` as the active account, clearing any pending one
+pub async fn activate(pool: &PgPool, number: &str) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO signal_account (id, phone_number, pending_number) VALUES (TRUE, $1, NULL) \
+         ON CONFLICT (id) DO UPDATE SET phone_number = $1, pending_number = NULL, updated_at = NOW()",
+    )
+    .bind(number)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record `number` as waiting for its verification code
+async fn set_pending(pool: &PgPool, number: &str) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO signal_account (id, pending_number) VALUES (TRUE, $1) \
+         ON CONFLICT (id) DO UPDATE SET pending_number = $1, updated_at = NOW()",
+    )
+    .bind(number)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Request to start using a new number
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateNumberRequest {
+    /// Number to register, with country code (e.g. "+15550001111")
+    pub phone_number: String,
+    /// Captcha token, if Signal rejected an earlier attempt without one
+    #[serde(default)]
+    pub captcha: Option<String>,
+    /// Ask for a voice call instead of an SMS
+    #[serde(default)]
+    pub voice: bool,
+}
+
+/// Request to complete registration with the code Signal sent
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyRequest {
+    /// Number the code was sent to
+    pub phone_number: String,
+    /// The verification code, with or without the dash
+    pub verification_code: String,
+    /// Registration lock PIN, if the number has one
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+/// Outcome of a registration step, shown to the operator
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountResponse {
+    pub message: String,
+}
+
+/// Where the bot's Signal account stands
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountStatus {
+    /// Summary for display
+    pub status: String,
+    /// Number the bot runs as
+    pub phone_number: Option<String>,
+    /// Number waiting for its verification code
+    pub pending_number: Option<String>,
+    /// Whether signal-cli has the active number registered
+    pub registered: bool,
+}
+
+/// Request to link the bot to an existing account
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkRequest {
+    /// Name shown in the phone's list of linked devices
+    #[serde(default)]
+    pub device_name: Option<String>,
+}
+
+/// Where linking as a secondary device stands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkState {
+    /// No link has been started
+    #[default]
+    Idle,
+    /// Waiting for the phone to scan the QR code
+    Waiting,
+    /// Linked; the bot now runs as the linked account
+    Linked,
+    /// signal-cli gave up, e.g. because the QR code expired
+    Failed,
+}
+
+/// Progress of linking the bot as a secondary device
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkStatus {
+    pub state: LinkState,
+    /// Provisioning URI to scan, while waiting
+    pub uri: Option<String>,
+    /// The provisioning URI rendered as an SVG QR code, while waiting
+    pub qr_svg: Option<String>,
+    /// Number of the linked account, once linked
+    pub phone_number: Option<String>,
+    /// Why linking failed
+    pub error: Option<String>,
+}
+
+impl LinkStatus {
+    /// Add the QR code while the URI is still waiting to be scanned
+    fn with_qr(mut self) -> Self {
+        if self.state == LinkState::Waiting {
+            self.qr_svg = self.uri.as_deref().and_then(qr_svg);
+        }
+        self
+    }
+}
+
+/// Render `data` as an SVG QR code
+fn qr_svg(data: &str) -> Option<String> {
+    let code = QrCode::new(data.as_bytes()).ok()?;
+    Some(code.render::<svg::Color>().min_dimensions(256, 256).build())
+}
+
+fn account_manager(state: &AppState) -> AppResult<&Arc<SignalAccount>> {
+    state
+        .account
+        .as_ref()
+        .ok_or_else(|| AppError::config("Signal account management is not enabled"))
+}
+
+fn validate_number(number: &str) -> AppResult<()> {
+    let digits = number.strip_prefix('+').ok_or_else(|| {
+        AppError::validation(
+            "phoneNumber",
+            "Phone number must start with '+' and include country code",
+        )
+    })?;
+    if digits.len() < 7 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AppError::validation(
+            "phoneNumber",
+            "Phone number must contain only digits after the '+'",
+        ));
+    }
+    Ok(())
+}
+
+/// Ask Signal to send a verification code to a new number
+///
+/// The number stays pending, and the bot keeps running as the current one,
+/// until the code is verified.
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the phone number is malformed
+/// * `AppError::Signal` - If Signal refuses, e.g. because a captcha is needed
+/// * `AppError::Config` - If account management is not enabled, or the
+///   transport cannot register numbers
+///
+/// # Example
+///
+/// ```json
+/// POST /api/signal/update-number
+/// {
+///   "phoneNumber": "+15550001111",
+///   "captcha": "signalcaptcha://...",
+///   "voice": false
+/// }
+///
+/// Response:
+/// {
+///   "message": "Verification code sent to +15550001111 by SMS"
+/// }
+/// ```
+pub async fn update_number(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateNumberRequest>,
+) -> AppResult<Json<AccountResponse>> {
+    let account = account_manager(&state)?;
+    let number = payload.phone_number.trim();
+    validate_number(number)?;
+
+    let options = RegisterOptions {
+        captcha: payload
+            .captcha
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty()),
+        voice: payload.voice,
+    };
+    account
+        .registrar()?
+        .register(number, &options)
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    set_pending(&state.pool, number)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    info!("📨 Verification code requested for {}", number);
+
+    let channel = if options.voice { "voice call" } else { "SMS" };
+    Ok(Json(AccountResponse {
+        message: format!("Verification code sent to {number} by {channel}"),
+    }))
+}
+
+/// Verify a pending number and make it the bot's account
+///
+/// The profile set through `PUT /api/signal/profile`, if any, is applied to
+/// the new number.
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the number is not the pending one or the
+///   code is malformed
+/// * `AppError::Signal` - If Signal rejects the code
+/// * `AppError::Config` - If account management is not enabled, or the
+///   transport cannot register numbers
+///
+/// # Example
+///
+/// ```json
+/// POST /api/signal/verify
+/// {
+///   "phoneNumber": "+15550001111",
+///   "verificationCode": "123-456"
+/// }
+///
+/// Response:
+/// {
+///   "message": "+15550001111 is verified and now active"
+/// }
+/// ```
+pub async fn verify_number(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifyRequest>,
+) -> AppResult<Json<AccountResponse>> {
+    let account = account_manager(&state)?;
+    let number = payload.phone_number.trim();
+    let code: String = payload
+        .verification_code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+    if code.is_empty() || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AppError::validation(
+            "verificationCode",
+            "Verification code must be digits",
+        ));
+    }
+
+    let stored = stored_account(&state.pool)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    if stored.pending_number.as_deref() != Some(number) {
+        return Err(AppError::validation(
+            "phoneNumber",
+            "No verification code was requested for this number",
+        ));
+    }
+
+    account
+        .registrar()?
+        .verify(number, &code, payload.pin.as_deref())
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    activate(&state.pool, number)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    account.switch_to(number);
+    // A newly registered number starts without a name or avatar
+    if let Err(e) = profile::reapply(&state.pool, state.signal.as_ref()).await {
+        warn!("⚠️  Could not re-apply the Signal profile: {}", e);
+    }
+
+    Ok(Json(AccountResponse {
+        message: format!("{number} is verified and now active"),
+    }))
+}
+
+/// Report the active and pending numbers and whether signal-cli has the
+/// active one registered
+///
+/// # Example
+///
+/// ```json
+/// GET /api/signal/status
+///
+/// Response:
+/// {
+///   "status": "Running as +15550000000",
+///   "phoneNumber": "+15550000000",
+///   "pendingNumber": null,
+///   "registered": true
+/// }
+/// ```
+pub async fn account_status(State(state): State<AppState>) -> AppResult<Json<AccountStatus>> {
+    let account = account_manager(&state)?;
+    let stored = stored_account(&state.pool)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+
+    let registered = match (&stored.phone_number, &account.registrar) {
+        (Some(number), Some(registrar)) => {
+            registrar.is_registered(number).await.unwrap_or_else(|e| {
+                error!("❌ Could not check registration of {}: {}", number, e);
+                false
+            })
+        }
+        // The transport keeps its own account, which the number must be
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+
+    let mut status = match (&stored.phone_number, registered) {
+        (Some(number), true) => format!("Running as {number}"),
+        (Some(number), false) => format!("{number} is not registered with signal-cli"),
+        (None, _) => "No Signal number registered yet".to_string(),
+    };
+    if let Some(pending) = &stored.pending_number {
+        status.push_str(&format!(
+            "; waiting for the verification code sent to {pending}"
+        ));
+    }
+
+    Ok(Json(AccountStatus {
+        status,
+        phone_number: stored.phone_number,
+        pending_number: stored.pending_number,
+        registered,
+    }))
+}
+
+/// Start linking the bot to an existing Signal account as a secondary device
+///
+/// Returns the provisioning URI and its QR code. Scan it from the phone under
+/// Settings > Linked devices; once the phone confirms, the bot switches to the
+/// linked account. If a link is already waiting, its QR code is returned
+/// again.
+///
+/// # Errors
+///
+/// * `AppError::Signal` - If signal-cli cannot start linking
+/// * `AppError::Config` - If account management is not enabled, or the
+///   transport cannot register numbers
+///
+/// # Example
+///
+/// ```json
+/// POST /api/signal/link
+/// {
+///   "deviceName": "Senator Budd Bot"
+/// }
+///
+/// Response:
+/// {
+///   "state": "waiting",
+///   "uri": "sgnl://linkdevice?uuid=...&pub_key=...",
+///   "qrSvg": "<?xml version=...",
+///   "phoneNumber": null,
+///   "error": null
+/// }
+/// ```
+pub async fn start_link(
+    State(state): State<AppState>,
+    Json(payload): Json<LinkRequest>,
+) -> AppResult<Json<LinkStatus>> {
+    let account = account_manager(&state)?.clone();
+    let mut link = account.link.lock().await;
+    if link.state == LinkState::Waiting {
+        return Ok(Json(link.clone().with_qr()));
+    }
+
+    let device_name = payload
+        .device_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_DEVICE_NAME);
+    let pending = account
+        .registrar()?
+        .link(device_name)
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    info!("🔗 Waiting for a phone to link \"{}\"", device_name);
+
+    *link = LinkStatus {
+        state: LinkState::Waiting,
+        uri: Some(pending.uri),
+        ..Default::default()
+    };
+    let status = link.clone().with_qr();
+    drop(link);
+
+    let pool = state.pool.clone();
+    tokio::spawn(async move {
+        let outcome = match pending.linked.await {
+            Ok(number) => match activate(&pool, &number).await {
+                Ok(()) => {
+                    account.switch_to(&number);
+                    info!("🔗 Linked as a device of {}", number);
+                    Ok(number)
+                }
+                Err(e) => Err(anyhow::anyhow!("failed to store linked account: {e}")),
+            },
+            Err(e) => Err(e),
+        };
+
+        let mut link = account.link.lock().await;
+        *link = match outcome {
+            Ok(number) => LinkStatus {
+                state: LinkState::Linked,
+                phone_number: Some(number),
+                ..Default::default()
+            },
+            Err(e) => {
+                warn!("⚠️  Device linking failed: {}", e);
+                LinkStatus {
+                    state: LinkState::Failed,
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                }
+            }
+        };
+    });
+
+    Ok(Json(status))
+}
+
+/// Report progress of the latest device link
+///
+/// The frontend polls this after showing the QR code.
+///
+/// # Example
+///
+/// ```json
+/// GET /api/signal/link
+///
+/// Response:
+/// {
+///   "state": "linked",
+///   "uri": null,
+///   "qrSvg": null,
+///   "phoneNumber": "+15550000000",
+///   "error": null
+/// }
+/// ```
+pub async fn link_status(State(state): State<AppState>) -> AppResult<Json<LinkStatus>> {
+    let account = account_manager(&state)?;
+    let link = account.link.lock().await.clone();
+    Ok(Json(link.with_qr()))
+}
@@ -0,0 +1,307 @@
+//! Runtime configuration for the bot's behaviour
+//!
+//! Values are read from environment variables at startup. `Default` gives the
+//! behaviour used when a variable is unset, which is also what tests get.
+
+use crate::error::{AppError, AppResult};
+use crate::signal::SignalMessage;
+use std::str::FromStr;
+
+/// Signal sends bodies longer than about 2000 characters as a long-text
+/// attachment, which older clients show as a file to open
+const DEFAULT_MAX_MESSAGE_CHARS: usize = 2000;
+
+/// With the outbox's doubling retry delay, 8 attempts span about an hour
+const DEFAULT_OUTBOX_MAX_ATTEMPTS: u32 = 8;
+
+/// Messages per minute to one recipient; a conversation never needs more
+const DEFAULT_SEND_LIMIT_PER_RECIPIENT: u32 = 20;
+
+/// Messages per minute overall, well below where Signal starts asking for
+/// captchas
+const DEFAULT_SEND_LIMIT_GLOBAL: u32 = 120;
+
+/// Earlier exchanges sent along with each question, enough to follow up on
+/// anything from the last few minutes of conversation
+const DEFAULT_CONTEXT_TURNS: u32 = 10;
+
+/// Tokens of history and question sent to the LLM, a small share of its
+/// context window
+const DEFAULT_CONTEXT_TOKENS: u32 = 8000;
+
+/// When the bot answers messages posted in a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupReplyPolicy {
+    /// Answer every group message
+    All,
+    /// Answer only when the bot is @-mentioned
+    #[default]
+    Mentioned,
+    /// Never answer in groups
+    Never,
+}
+
+impl FromStr for GroupReplyPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "all" => Ok(Self::All),
+            "mentioned" => Ok(Self::Mentioned),
+            "never" => Ok(Self::Never),
+            other => Err(AppError::config(format!(
+                "invalid group reply policy '{other}' (expected 'all', 'mentioned' or 'never')"
+            ))),
+        }
+    }
+}
+
+/// What happens to messages from senders with no contacts entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownSenderPolicy {
+    /// Answer them like anyone else
+    #[default]
+    Allow,
+    /// Drop their messages without a reply
+    Ignore,
+    /// Send a fixed reply instead of an answer
+    Reply,
+    /// Ignore them, but add them to the contacts list for an admin to approve
+    Approve,
+}
+
+impl FromStr for UnknownSenderPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(Self::Allow),
+            "ignore" => Ok(Self::Ignore),
+            "reply" => Ok(Self::Reply),
+            "approve" => Ok(Self::Approve),
+            other => Err(AppError::config(format!(
+                "invalid unknown sender policy '{other}' \
+                 (expected 'allow', 'ignore', 'reply' or 'approve')"
+            ))),
+        }
+    }
+}
+
+/// What to do when a contact's identity key changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdentityTrustPolicy {
+    /// Trust the new key straight away, as Signal apps do by default
+    Auto,
+    /// Wait for an admin to trust it through `/identities`
+    #[default]
+    Approve,
+    /// Never trust changed keys; the contact stays unreachable
+    Refuse,
+}
+
+impl FromStr for IdentityTrustPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "approve" => Ok(Self::Approve),
+            "refuse" => Ok(Self::Refuse),
+            other => Err(AppError::config(format!(
+                "invalid identity trust policy '{other}' (expected 'auto', 'approve' or 'refuse')"
+            ))),
+        }
+    }
+}
+
+/// Sent to unknown senders under [`UnknownSenderPolicy::Reply`]
+const DEFAULT_UNKNOWN_SENDER_REPLY: &str =
+    "Sorry, this number only answers people it has been set up for.";
+
+/// Bot behaviour settings shared with the worker and handlers
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    /// When to answer messages posted in groups
+    pub group_reply_policy: GroupReplyPolicy,
+    /// The bot account's ACI uuid, used to recognise @-mentions and the
+    /// bot's own messages when they carry no phone number. Without it, a
+    /// message from our own account that only carries its uuid would be
+    /// answered.
+    pub account_uuid: Option<String>,
+    /// Answer edited questions again, deleting the stale answer
+    pub answer_edits: bool,
+    /// Longest message body sent in one piece; longer replies are split
+    pub max_message_chars: usize,
+    /// How to treat senders that are neither allowed nor blocked
+    pub unknown_senders: UnknownSenderPolicy,
+    /// Reply sent to unknown senders under [`UnknownSenderPolicy::Reply`]
+    pub unknown_sender_reply: String,
+    /// Whether changed identity keys are trusted automatically
+    pub identity_trust: IdentityTrustPolicy,
+    /// Delivery attempts before an outgoing message is dead-lettered
+    pub outbox_max_attempts: u32,
+    /// Most messages sent to one recipient per minute, 0 for no limit
+    pub send_limit_per_recipient: u32,
+    /// Most messages sent per minute in total, 0 for no limit
+    pub send_limit_global: u32,
+    /// Most earlier exchanges the LLM is shown with a question, 0 for none
+    pub context_turns: u32,
+    /// Rough token budget for the question and its history, 0 for no limit
+    pub context_tokens: u32,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            group_reply_policy: GroupReplyPolicy::default(),
+            account_uuid: None,
+            answer_edits: false,
+            max_message_chars: DEFAULT_MAX_MESSAGE_CHARS,
+            unknown_senders: UnknownSenderPolicy::default(),
+            unknown_sender_reply: DEFAULT_UNKNOWN_SENDER_REPLY.to_string(),
+            identity_trust: IdentityTrustPolicy::default(),
+            outbox_max_attempts: DEFAULT_OUTBOX_MAX_ATTEMPTS,
+            send_limit_per_recipient: DEFAULT_SEND_LIMIT_PER_RECIPIENT,
+            send_limit_global: DEFAULT_SEND_LIMIT_GLOBAL,
+            context_turns: DEFAULT_CONTEXT_TURNS,
+            context_tokens: DEFAULT_CONTEXT_TOKENS,
+        }
+    }
+}
+
+impl BotConfig {
+    /// Load settings from the environment, falling back to defaults
+    ///
+    /// * `SIGNAL_GROUP_REPLY_POLICY` - `all`, `mentioned` (default) or `never`
+    /// * `SIGNAL_ACCOUNT_UUID` - the bot's own ACI uuid, needed to never
+    ///   answer itself
+    /// * `SIGNAL_ANSWER_EDITS` - `true` to re-answer edited questions
+    /// * `SIGNAL_MAX_MESSAGE_CHARS` - split replies longer than this (default 2000)
+    /// * `SIGNAL_UNKNOWN_SENDERS` - `allow` (default), `ignore`, `reply` or `approve`
+    /// * `SIGNAL_UNKNOWN_SENDER_REPLY` - the reply sent under the `reply` policy
+    /// * `SIGNAL_IDENTITY_TRUST` - `auto`, `approve` (default) or `refuse`
+    /// * `SIGNAL_OUTBOX_MAX_ATTEMPTS` - give up delivering a message after this
+    ///   many attempts (default 8)
+    /// * `SIGNAL_SEND_LIMIT_PER_RECIPIENT` - messages per minute to one
+    ///   recipient (default 20, 0 for no limit)
+    /// * `SIGNAL_SEND_LIMIT_GLOBAL` - messages per minute in total (default 120,
+    ///   0 for no limit)
+    /// * `LLM_CONTEXT_TURNS` - earlier exchanges sent with each question
+    ///   (default 10, 0 for none)
+    /// * `LLM_CONTEXT_TOKENS` - token budget for a question and its history
+    ///   (default 8000, 0 for no limit)
+    pub fn from_env() -> AppResult<Self> {
+        let group_reply_policy = match std::env::var("SIGNAL_GROUP_REPLY_POLICY") {
+            Ok(value) => value.parse()?,
+            Err(_) => GroupReplyPolicy::default(),
+        };
+        let max_message_chars = match std::env::var("SIGNAL_MAX_MESSAGE_CHARS") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&chars| chars > 0)
+                .ok_or_else(|| {
+                    AppError::config(format!(
+                        "SIGNAL_MAX_MESSAGE_CHARS must be a positive number, got '{value}'"
+                    ))
+                })?,
+            Err(_) => DEFAULT_MAX_MESSAGE_CHARS,
+        };
+        let unknown_senders = match std::env::var("SIGNAL_UNKNOWN_SENDERS") {
+            Ok(value) => value.parse()?,
+            Err(_) => UnknownSenderPolicy::default(),
+        };
+        let identity_trust = match std::env::var("SIGNAL_IDENTITY_TRUST") {
+            Ok(value) => value.parse()?,
+            Err(_) => IdentityTrustPolicy::default(),
+        };
+        let outbox_max_attempts = match std::env::var("SIGNAL_OUTBOX_MAX_ATTEMPTS") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|&attempts| attempts > 0)
+                .ok_or_else(|| {
+                    AppError::config(format!(
+                        "SIGNAL_OUTBOX_MAX_ATTEMPTS must be a positive number, got '{value}'"
+                    ))
+                })?,
+            Err(_) => DEFAULT_OUTBOX_MAX_ATTEMPTS,
+        };
+
+        Ok(Self {
+            group_reply_policy,
+            account_uuid: std::env::var("SIGNAL_ACCOUNT_UUID").ok(),
+            answer_edits: env_flag("SIGNAL_ANSWER_EDITS")?,
+            max_message_chars,
+            unknown_senders,
+            unknown_sender_reply: std::env::var("SIGNAL_UNKNOWN_SENDER_REPLY")
+                .ok()
+                .filter(|reply| !reply.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_UNKNOWN_SENDER_REPLY.to_string()),
+            identity_trust,
+            outbox_max_attempts,
+            send_limit_per_recipient: env_limit(
+                "SIGNAL_SEND_LIMIT_PER_RECIPIENT",
+                DEFAULT_SEND_LIMIT_PER_RECIPIENT,
+            )?,
+            send_limit_global: env_limit("SIGNAL_SEND_LIMIT_GLOBAL", DEFAULT_SEND_LIMIT_GLOBAL)?,
+            context_turns: env_limit("LLM_CONTEXT_TURNS", DEFAULT_CONTEXT_TURNS)?,
+            context_tokens: env_limit("LLM_CONTEXT_TOKENS", DEFAULT_CONTEXT_TOKENS)?,
+        })
+    }
+
+    /// Whether the bot should answer `message`
+    ///
+    /// Direct messages are always answered; group messages follow
+    /// [`BotConfig::group_reply_policy`].
+    pub fn should_reply(&self, message: &SignalMessage) -> bool {
+        if message.group_id.is_none() {
+            return true;
+        }
+
+        match self.group_reply_policy {
+            GroupReplyPolicy::All => true,
+            GroupReplyPolicy::Never => false,
+            GroupReplyPolicy::Mentioned => message.mentions_any(&self.own_identities(message)),
+        }
+    }
+
+    /// The bot's own number and uuid, as seen by `message`
+    pub fn own_identities<'a>(&'a self, message: &'a SignalMessage) -> Vec<&'a str> {
+        let mut identities = vec![message.to.as_str()];
+        if let Some(uuid) = &self.account_uuid {
+            identities.push(uuid);
+        }
+        identities
+    }
+
+    /// Whether `message` came from the bot's own account, e.g. a note to self
+    /// or a message sent from the phone the bot is linked to
+    pub fn is_own_message(&self, message: &SignalMessage) -> bool {
+        let own = self.own_identities(message);
+        message.sender_ids().iter().any(|id| own.contains(id))
+    }
+}
+
+/// Read a `true`/`false` environment variable, defaulting to `false`
+fn env_flag(name: &str) -> AppResult<bool> {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().map_err(|_| {
+            AppError::config(format!("{name} must be 'true' or 'false', got '{value}'"))
+        }),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Read a limit or count from the environment
+fn env_limit(name: &str, default: u32) -> AppResult<u32> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| AppError::config(format!("{name} must be a number, got '{value}'"))),
+        Err(_) => Ok(default),
+    }
+}
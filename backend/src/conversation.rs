@@ -0,0 +1,119 @@
+//! What the Senator remembers of a conversation
+//!
+//! Each question goes to the LLM along with the sender's earlier questions
+//! and the answers they got, so follow-ups like "and what about the
+//! budget?" make sense. History is loaded from the stored messages and
+//! trimmed, oldest exchanges first, to [`BotConfig::context_turns`]
+//! exchanges and about [`BotConfig::context_tokens`] tokens.
+//!
+//! [`BotConfig::context_turns`]: crate::config::BotConfig::context_turns
+//! [`BotConfig::context_tokens`]: crate::config::BotConfig::context_tokens
+
+use crate::config::BotConfig;
+use crate::llm::Turn;
+use sqlx::PgPool;
+
+/// Instructions the Senator answers every message under
+pub const SENATOR_PERSONA: &str =
+    "You are Senator Ted Budd of North Carolina. Respond to this message as the Senator would, \
+    keeping in mind you're helping prepare Vice Admiral Mitch Bradley for his confirmation hearing \
+    for Admiral and Commander of SOCOM. Be professional, knowledgeable about military affairs, \
+    and supportive.";
+
+/// Where a conversation happens: with a Signal sender, directly or in a
+/// group, or in the web chat when `sender` is `None`
+#[derive(Debug, Clone, Copy)]
+pub struct Conversation<'a> {
+    pub sender: Option<&'a str>,
+    pub group_id: Option<&'a str>,
+}
+
+/// Rough token count of `text`, at about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// The turns to send for `message`: as much of `history` as the budget
+/// allows, followed by `message` itself
+///
+/// `history` holds earlier exchanges as question and answer pairs, oldest
+/// first. Whole exchanges are dropped from the oldest end until at most
+/// `max_turns` remain and, unless `max_tokens` is 0, everything fits in
+/// `max_tokens`. The message itself is always sent.
+pub fn fit_budget(
+    history: Vec<(String, String)>,
+    message: &str,
+    max_turns: usize,
+    max_tokens: usize,
+) -> Vec<Turn> {
+    let mut budget = match max_tokens {
+        0 => usize::MAX,
+        limit => limit.saturating_sub(estimate_tokens(message)),
+    };
+    let mut kept = Vec::new();
+    for (question, answer) in history.into_iter().rev().take(max_turns) {
+        let tokens = estimate_tokens(&question) + estimate_tokens(&answer);
+        if tokens > budget {
+            break;
+        }
+        budget -= tokens;
+        kept.push((question, answer));
+    }
+
+    kept.into_iter()
+        .rev()
+        .flat_map(|(question, answer)| [Turn::user(question), Turn::assistant(answer)])
+        .chain(std::iter::once(Turn::user(message)))
+        .collect()
+}
+
+/// Earlier exchanges in `conversation`, oldest first
+///
+/// Only questions that got an answer count, each with its latest answer
+/// still standing. `replacing` leaves out the question an edit replaces.
+pub async fn history(
+    pool: &PgPool,
+    conversation: Conversation<'_>,
+    replacing: Option<u64>,
+    limit: usize,
+) -> anyhow::Result<Vec<(String, String)>> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    let mut exchanges: Vec<(String, String)> = sqlx::query_as(
+        "SELECT q.content, a.content FROM messages q \
+         JOIN LATERAL (SELECT content FROM messages \
+             WHERE reply_to = q.id AND role = 'assistant' AND deleted_at IS NULL \
+             ORDER BY created_at DESC LIMIT 1) a ON TRUE \
+         WHERE q.role = 'user' AND q.sender IS NOT DISTINCT FROM $1 \
+         AND q.group_id IS NOT DISTINCT FROM $2 AND q.deleted_at IS NULL AND q.content <> '' \
+         AND ($3::BIGINT IS NULL OR q.signal_timestamp IS DISTINCT FROM $3) \
+         ORDER BY q.created_at DESC LIMIT $4",
+    )
+    .bind(conversation.sender)
+    .bind(conversation.group_id)
+    .bind(replacing.map(|timestamp| timestamp as i64))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    exchanges.reverse();
+    Ok(exchanges)
+}
+
+/// The turns to send the LLM for `message` in `conversation`
+pub async fn turns(
+    pool: &PgPool,
+    config: &BotConfig,
+    conversation: Conversation<'_>,
+    replacing: Option<u64>,
+    message: &str,
+) -> anyhow::Result<Vec<Turn>> {
+    let turns = config.context_turns as usize;
+    let history = history(pool, conversation, replacing, turns).await?;
+    Ok(fit_budget(
+        history,
+        message,
+        turns,
+        config.context_tokens as usize,
+    ))
+}
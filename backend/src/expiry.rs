@@ -0,0 +1,94 @@
+//! Disappearing messages
+//!
+//! When a conversation has disappearing messages turned on, Signal removes
+//! messages from everyone's devices once the timer runs out, and keeping them
+//! in the database would defeat that. Each incoming message carries its
+//! conversation's timer: stored messages get an `expires_at` from it, replies
+//! are sent with the same timer, and [`start_purge_task`] deletes stored
+//! messages once they expire.
+
+use crate::signal::{Recipient, SignalClient, SignalMessage};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// How often expired messages are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The key a conversation's timer is stored under: the group id, or the
+/// other person for direct messages
+fn conversation_key(recipient: &Recipient) -> &str {
+    match recipient {
+        Recipient::Direct(to) => to,
+        Recipient::Group(group_id) => group_id,
+    }
+}
+
+/// The timer last set for the conversation with `recipient`, 0 when off
+pub async fn conversation_timer(pool: &PgPool, recipient: &Recipient) -> anyhow::Result<u32> {
+    let seconds: Option<i32> = sqlx::query_scalar(
+        "SELECT expires_in_seconds FROM conversation_timers WHERE conversation = $1",
+    )
+    .bind(conversation_key(recipient))
+    .fetch_optional(pool)
+    .await?;
+    Ok(seconds.unwrap_or(0) as u32)
+}
+
+/// Send our replies to `message` with the timer its conversation uses
+///
+/// The timer is only set in Signal when it differs from the one set last.
+pub async fn sync_timer(
+    pool: &PgPool,
+    signal: &dyn SignalClient,
+    message: &SignalMessage,
+) -> anyhow::Result<()> {
+    let seconds = message.expires_in_seconds.unwrap_or(0);
+    let recipient = message.reply_recipient();
+    if conversation_timer(pool, &recipient).await? == seconds {
+        return Ok(());
+    }
+
+    signal.set_expiration(&recipient, seconds).await?;
+    sqlx::query(
+        "INSERT INTO conversation_timers (conversation, expires_in_seconds) VALUES ($1, $2) \
+         ON CONFLICT (conversation) DO UPDATE SET expires_in_seconds = EXCLUDED.expires_in_seconds, \
+         updated_at = NOW()",
+    )
+    .bind(conversation_key(&recipient))
+    .bind(seconds as i32)
+    .execute(pool)
+    .await?;
+    info!(
+        "⏳ Replies to {} now disappear after {}s",
+        recipient, seconds
+    );
+    Ok(())
+}
+
+/// Delete stored messages whose timer ran out, returning how many
+///
+/// Their attachments and feedback go with them; answers to a deleted
+/// question stay until their own timer runs out.
+pub async fn purge_expired(pool: &PgPool) -> anyhow::Result<u64> {
+    let purged = sqlx::query("DELETE FROM messages WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+    Ok(purged)
+}
+
+/// Purge expired messages every minute
+pub async fn start_purge_task(pool: PgPool) {
+    info!("⏳ Disappearing message purge started");
+
+    loop {
+        match purge_expired(&pool).await {
+            Ok(0) => debug!("⏳ No expired messages to purge"),
+            Ok(purged) => info!("🗑️  Purged {} expired messages", purged),
+            Err(e) => error!("❌ Failed to purge expired messages: {}", e),
+        }
+        sleep(PURGE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,257 @@
+//! Emoji reactions on the Senator's answers, kept as feedback
+//!
+//! When someone reacts to one of the bot's Signal replies, the reaction is
+//! matched to the stored assistant message through the timestamp Signal
+//! assigned when the reply was sent. Only reactions to the bot's own
+//! messages count, from someone in the conversation the answer went to: the
+//! person who asked, or the group it was posted in. Each person keeps at
+//! most one reaction per answer, mirroring how Signal itself treats
+//! reactions.
+
+use crate::config::BotConfig;
+use crate::error::{AppError, AppResult};
+use crate::signal::{SignalMessage, SignalReaction};
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeMap;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+/// Reactions counted as "this answer helped"
+const POSITIVE_EMOJI: &[&str] = &["👍", "❤️", "❤", "🔥", "💯", "👏", "🙏", "😂"];
+
+/// Reactions counted as "this answer missed"
+const NEGATIVE_EMOJI: &[&str] = &["👎", "😡", "😢", "🤦", "❌"];
+
+/// +1 for a positive reaction, -1 for a negative one, 0 otherwise
+pub fn emoji_score(emoji: &str) -> i64 {
+    if POSITIVE_EMOJI.contains(&emoji) {
+        1
+    } else if NEGATIVE_EMOJI.contains(&emoji) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Record (or take back) the reaction `message` carries to one of our
+/// answers
+///
+/// Returns `false` when the reaction targets a message we have no record
+/// of, e.g. something the user sent or a reply sent before timestamps were
+/// stored, or an answer that went to another conversation.
+pub async fn record_reaction(
+    pool: &PgPool,
+    config: &BotConfig,
+    message: &SignalMessage,
+    reaction: &SignalReaction,
+) -> anyhow::Result<bool> {
+    // Timestamps are only unique per author, so anyone else's message sent
+    // in the same millisecond would match too
+    if !config
+        .own_identities(message)
+        .contains(&reaction.target_author.as_str())
+    {
+        debug!(
+            "🤷 Reaction to {}'s message is not feedback",
+            reaction.target_author
+        );
+        return Ok(false);
+    }
+
+    let reactor = message.from.as_str();
+    let message_id: Option<String> = sqlx::query_scalar(
+        "SELECT a.id FROM messages a LEFT JOIN messages q ON q.id = a.reply_to \
+         WHERE a.role = 'assistant' \
+         AND (a.signal_timestamp = $1 OR a.part_timestamps @> ARRAY[$1]) \
+         AND a.group_id IS NOT DISTINCT FROM $2 \
+         AND (a.group_id IS NOT NULL OR q.sender = ANY($3)) LIMIT 1",
+    )
+    .bind(reaction.target_timestamp as i64)
+    .bind(&message.group_id)
+    .bind(message.sender_ids())
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(message_id) = message_id else {
+        debug!(
+            "🤷 No stored answer for reaction target {}",
+            reaction.target_timestamp
+        );
+        return Ok(false);
+    };
+
+    if reaction.remove {
+        sqlx::query("DELETE FROM feedback WHERE message_id = $1 AND reactor = $2")
+            .bind(&message_id)
+            .bind(reactor)
+            .execute(pool)
+            .await?;
+        info!("↩️  {} removed their reaction to {}", reactor, message_id);
+    } else {
+        sqlx::query(
+            "INSERT INTO feedback (id, message_id, reactor, emoji) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (message_id, reactor) DO UPDATE SET emoji = EXCLUDED.emoji, created_at = NOW()",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&message_id)
+        .bind(reactor)
+        .bind(&reaction.emoji)
+        .execute(pool)
+        .await?;
+        info!(
+            "{} {} reacted to answer {}",
+            reaction.emoji, reactor, message_id
+        );
+    }
+    Ok(true)
+}
+
+/// How many times one emoji was used on one answer
+#[derive(FromRow)]
+struct ReactionCount {
+    message_id: String,
+    content: String,
+    created_at: DateTime<Utc>,
+    emoji: String,
+    count: i64,
+}
+
+/// Query parameters for the feedback report
+#[derive(Debug, Deserialize)]
+pub struct FeedbackQuery {
+    /// Only include answers whose reactions net out negative
+    #[serde(default)]
+    pub negative_only: bool,
+    /// Maximum number of answers to return (1-500, default 50)
+    pub limit: Option<usize>,
+}
+
+/// Reactions collected for a single answer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnswerFeedback {
+    /// Id of the assistant message
+    pub message_id: String,
+    /// The answer that was reacted to
+    pub content: String,
+    /// When the answer was stored
+    pub created_at: DateTime<Utc>,
+    /// Positive minus negative reactions
+    pub score: i64,
+    /// Reaction counts keyed by emoji
+    pub reactions: BTreeMap<String, i64>,
+}
+
+/// Aggregate feedback across all answers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedbackReport {
+    /// Total number of reactions on record
+    pub total_reactions: i64,
+    /// Reactions counted as positive
+    pub positive: i64,
+    /// Reactions counted as negative
+    pub negative: i64,
+    /// Answers that received reactions, lowest score first
+    pub answers: Vec<AnswerFeedback>,
+}
+
+/// Review how the Senator's answers were received
+///
+/// Aggregates the emoji reactions stored for each answer and lists the
+/// answers lowest-scored first, so poorly rated ones are easy to find.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the database pool
+/// * `query` - `negative_only` and `limit` filters
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If `limit` is outside 1-500
+/// * `AppError::Database` - If the feedback query fails
+///
+/// # Example
+///
+/// ```json
+/// GET /feedback?negative_only=true&limit=10
+///
+/// Response:
+/// {
+///   "total_reactions": 12,
+///   "positive": 9,
+///   "negative": 3,
+///   "answers": [
+///     {
+///       "message_id": "6f1c...",
+///       "content": "As Senator Budd, I would...",
+///       "created_at": "2025-01-01T12:00:00Z",
+///       "score": -2,
+///       "reactions": { "👎": 2 }
+///     }
+///   ]
+/// }
+/// ```
+pub async fn feedback_report(
+    State(state): State<AppState>,
+    Query(query): Query<FeedbackQuery>,
+) -> AppResult<Json<FeedbackReport>> {
+    let limit = query.limit.unwrap_or(50);
+    if !(1..=500).contains(&limit) {
+        return Err(AppError::validation(
+            "limit",
+            "Limit must be between 1 and 500",
+        ));
+    }
+
+    let rows: Vec<ReactionCount> = sqlx::query_as(
+        "SELECT m.id AS message_id, m.content, m.created_at, f.emoji, COUNT(*) AS count \
+         FROM feedback f JOIN messages m ON m.id = f.message_id \
+         GROUP BY m.id, m.content, m.created_at, f.emoji",
+    )
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut report = FeedbackReport {
+        total_reactions: 0,
+        positive: 0,
+        negative: 0,
+        answers: Vec::new(),
+    };
+    let mut answers: BTreeMap<String, AnswerFeedback> = BTreeMap::new();
+    for row in rows {
+        let score = emoji_score(&row.emoji);
+        report.total_reactions += row.count;
+        match score {
+            1 => report.positive += row.count,
+            -1 => report.negative += row.count,
+            _ => {}
+        }
+
+        let answer = answers
+            .entry(row.message_id.clone())
+            .or_insert_with(|| AnswerFeedback {
+                message_id: row.message_id,
+                content: row.content,
+                created_at: row.created_at,
+                score: 0,
+                reactions: BTreeMap::new(),
+            });
+        answer.score += score * row.count;
+        answer.reactions.insert(row.emoji, row.count);
+    }
+
+    report.answers = answers
+        .into_values()
+        .filter(|answer| !query.negative_only || answer.score < 0)
+        .collect();
+    report
+        .answers
+        .sort_by(|a, b| a.score.cmp(&b.score).then(b.created_at.cmp(&a.created_at)));
+    report.answers.truncate(limit);
+
+    Ok(Json(report))
+}
@@ -0,0 +1,257 @@
+//! Identity key changes and whether to trust them
+//!
+//! When a contact reinstalls Signal or moves to a new phone their identity
+//! key (safety number) changes. Until the new key is trusted, their messages
+//! cannot be read and replies to them fail. Changes are noticed on both
+//! sides: envelopes flagged [`SignalMessage::untrusted_identity`] and sends
+//! failing with [`UntrustedIdentity`]. Each change is recorded and handled
+//! according to [`BotConfig::identity_trust`]: trusted right away, left for
+//! an admin to trust through `/identities`, or refused.
+//!
+//! [`SignalMessage::untrusted_identity`]: crate::signal::SignalMessage::untrusted_identity
+//! [`UntrustedIdentity`]: crate::signal::UntrustedIdentity
+//! [`BotConfig::identity_trust`]: crate::config::BotConfig::identity_trust
+
+use crate::config::IdentityTrustPolicy;
+use crate::error::{AppError, AppResult};
+use crate::signal::SignalClient;
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Where a recorded identity change stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum IdentityStatus {
+    /// Waiting for an admin to trust or refuse the new key
+    Pending,
+    /// The new key is trusted and the contact is reachable again
+    Trusted,
+    /// The new key will not be trusted
+    Refused,
+}
+
+/// How an identity change was noticed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ChangeSource {
+    /// A message from the contact could not be decrypted
+    Receive,
+    /// A reply to the contact was rejected
+    Send,
+}
+
+/// A contact's identity key change
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct IdentityChange {
+    pub id: String,
+    /// Number or ACI uuid whose key changed
+    pub number: String,
+    pub source: ChangeSource,
+    pub status: IdentityStatus,
+    /// Why trusting the new key last failed
+    pub error: Option<String>,
+    pub detected_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// Record that `number`'s identity key changed and apply `policy`
+///
+/// A change that is still pending or was refused is reused rather than
+/// recorded again. Returns the change; under [`IdentityTrustPolicy::Auto`]
+/// it comes back trusted, so a failed send can be retried.
+pub async fn identity_changed(
+    pool: &PgPool,
+    signal: &dyn SignalClient,
+    policy: IdentityTrustPolicy,
+    number: &str,
+    source: ChangeSource,
+) -> anyhow::Result<IdentityChange> {
+    let open: Option<IdentityChange> = sqlx::query_as(
+        "SELECT * FROM identity_changes WHERE number = $1 AND status <> 'trusted' \
+         ORDER BY detected_at DESC LIMIT 1",
+    )
+    .bind(number)
+    .fetch_optional(pool)
+    .await?;
+
+    let change = match open {
+        Some(change) => change,
+        None => {
+            let status = match policy {
+                IdentityTrustPolicy::Refuse => IdentityStatus::Refused,
+                IdentityTrustPolicy::Auto | IdentityTrustPolicy::Approve => IdentityStatus::Pending,
+            };
+            warn!("🔐 Identity key of {} changed", number);
+            sqlx::query_as(
+                "INSERT INTO identity_changes (id, number, source, status) VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (number) WHERE status = 'pending' \
+                 DO UPDATE SET number = EXCLUDED.number RETURNING *",
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(number)
+            .bind(source)
+            .bind(status)
+            .fetch_one(pool)
+            .await?
+        }
+    };
+
+    match policy {
+        IdentityTrustPolicy::Auto if change.status != IdentityStatus::Trusted => {
+            trust_change(pool, signal, &change).await
+        }
+        _ => Ok(change),
+    }
+}
+
+/// Trust the new key of `change` in Signal and mark it trusted
+///
+/// If Signal refuses, the error is kept on the change and returned.
+async fn trust_change(
+    pool: &PgPool,
+    signal: &dyn SignalClient,
+    change: &IdentityChange,
+) -> anyhow::Result<IdentityChange> {
+    if let Err(e) = signal.trust(&change.number).await {
+        sqlx::query("UPDATE identity_changes SET error = $1 WHERE id = $2")
+            .bind(e.to_string())
+            .bind(&change.id)
+            .execute(pool)
+            .await?;
+        return Err(e);
+    }
+
+    let trusted = sqlx::query_as(
+        "UPDATE identity_changes SET status = 'trusted', error = NULL, resolved_at = NOW() \
+         WHERE id = $1 RETURNING *",
+    )
+    .bind(&change.id)
+    .fetch_one(pool)
+    .await?;
+    info!("🔐 Trusted the new identity key of {}", change.number);
+    Ok(trusted)
+}
+
+/// Query parameters for listing identity changes
+#[derive(Debug, Deserialize)]
+pub struct IdentitiesQuery {
+    /// Only list changes with this status
+    pub status: Option<IdentityStatus>,
+}
+
+/// List identity key changes, newest first
+///
+/// # Example
+///
+/// ```json
+/// GET /identities?status=pending
+///
+/// Response:
+/// [
+///   {
+///     "id": "0b6e...",
+///     "number": "+15550001111",
+///     "source": "send",
+///     "status": "pending",
+///     "error": null,
+///     "detected_at": "2025-01-01T12:00:00Z",
+///     "resolved_at": null
+///   }
+/// ]
+/// ```
+pub async fn list_identity_changes(
+    State(state): State<AppState>,
+    Query(query): Query<IdentitiesQuery>,
+) -> AppResult<Json<Vec<IdentityChange>>> {
+    let changes = sqlx::query_as(
+        "SELECT * FROM identity_changes WHERE $1::TEXT IS NULL OR status = $1 \
+         ORDER BY detected_at DESC, id",
+    )
+    .bind(query.status)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(changes))
+}
+
+async fn find_change(pool: &PgPool, id: &str) -> AppResult<IdentityChange> {
+    let change: Option<IdentityChange> =
+        sqlx::query_as("SELECT * FROM identity_changes WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    change.ok_or_else(|| AppError::not_found(format!("identity change {id}")))
+}
+
+/// Trust a contact's new identity key, making them reachable again
+///
+/// Works for pending and refused changes alike.
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If there is no such change
+/// * `AppError::Signal` - If Signal refuses to trust the key
+///
+/// # Example
+///
+/// ```json
+/// POST /identities/0b6e.../trust
+///
+/// Response: the change, with `status` set to `trusted`
+/// ```
+pub async fn trust_identity(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<IdentityChange>> {
+    let change = find_change(&state.pool, &id).await?;
+    let trusted = trust_change(&state.pool, state.signal.as_ref(), &change)
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    Ok(Json(trusted))
+}
+
+/// Refuse a contact's new identity key
+///
+/// The contact stays unreachable until the change is trusted.
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If there is no such change
+/// * `AppError::Validation` - If the key was already trusted
+///
+/// # Example
+///
+/// ```json
+/// POST /identities/0b6e.../refuse
+///
+/// Response: the change, with `status` set to `refused`
+/// ```
+pub async fn refuse_identity(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<IdentityChange>> {
+    let change = find_change(&state.pool, &id).await?;
+    if change.status == IdentityStatus::Trusted {
+        return Err(AppError::validation(
+            "id",
+            "This identity key has already been trusted",
+        ));
+    }
+
+    let refused = sqlx::query_as(
+        "UPDATE identity_changes SET status = 'refused', resolved_at = NOW() \
+         WHERE id = $1 RETURNING *",
+    )
+    .bind(&id)
+    .fetch_one(&state.pool)
+    .await?;
+    info!("🔐 Refused the new identity key of {}", change.number);
+    Ok(Json(refused))
+}
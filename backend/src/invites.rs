@@ -0,0 +1,272 @@
+//! Invite codes for onboarding new Signal users
+//!
+//! Rather than adding each new staffer to the contacts list by hand, an admin
+//! issues an invite code through `POST /invites` and hands it over. The
+//! staffer's first message, `/join CODE`, redeems it: they become an allowed
+//! contact, and the contact records which invite they used and who issued it.
+//! Codes expire, can be limited to a number of uses, and can be revoked.
+
+use crate::access::ContactStatus;
+use crate::error::{AppError, AppResult};
+use crate::signal::SignalMessage;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::Json;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::info;
+
+/// Characters invite codes are made of, leaving out look-alikes like O and 0
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Length of a generated invite code
+const CODE_LENGTH: usize = 8;
+
+/// Invites expire after a week unless asked otherwise
+const DEFAULT_EXPIRY_HOURS: i64 = 7 * 24;
+
+/// An issued invite code
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Invite {
+    pub code: String,
+    /// Who issued the invite
+    pub created_by: Option<String>,
+    /// How many people can join with it, `None` for no limit
+    pub max_uses: Option<i32>,
+    /// How many people have joined with it
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request to issue an invite
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateInvite {
+    /// Who is issuing it, recorded on everyone who joins with it
+    pub created_by: Option<String>,
+    /// How many people can join with it (default 1)
+    pub max_uses: Option<i32>,
+    /// Hours until it expires (1-8760, default 168)
+    pub expires_in_hours: Option<i64>,
+}
+
+/// Outcome of a `/join CODE` message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redemption {
+    /// The sender is now allowed; `invited_by` issued the invite
+    Joined { invited_by: Option<String> },
+    /// The sender already had access, so the invite was not used
+    AlreadyAllowed,
+    /// The sender is blocked, which an invite does not override
+    Blocked,
+    /// The code does not exist, expired, was revoked or is used up
+    Invalid,
+}
+
+/// The invite a redemption used
+#[derive(FromRow)]
+struct UsedInvite {
+    code: String,
+    created_by: Option<String>,
+}
+
+/// The code in a `/join CODE` message, if `content` is one
+pub fn parse_join(content: &str) -> Option<&str> {
+    let mut words = content.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("/join") {
+        return None;
+    }
+    match (words.next(), words.next()) {
+        (Some(code), None) => Some(code),
+        _ => None,
+    }
+}
+
+/// A fresh random invite code
+fn generate_code() -> String {
+    uuid::Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(CODE_LENGTH)
+        .map(|&byte| CODE_ALPHABET[byte as usize % CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+/// Redeem `code` for the sender of `message`, making them an allowed contact
+///
+/// Like [`crate::access::check`], the sender's status is looked up under
+/// every id they go by, so a sender blocked under their number cannot join
+/// from their uuid. Codes are matched case-insensitively. Using the invite
+/// and allowing the sender happen together, so a code cannot be used more
+/// often than allowed.
+pub async fn redeem(
+    pool: &PgPool,
+    message: &SignalMessage,
+    code: &str,
+) -> anyhow::Result<Redemption> {
+    let sender = &message.from;
+    let mut tx = pool.begin().await?;
+
+    let status: Option<ContactStatus> = sqlx::query_scalar(
+        "SELECT status FROM contacts WHERE sender = ANY($1) \
+         ORDER BY status = 'blocked' DESC, status = 'allowed' DESC LIMIT 1 FOR UPDATE",
+    )
+    .bind(message.sender_ids())
+    .fetch_optional(&mut *tx)
+    .await?;
+    match status {
+        Some(ContactStatus::Allowed) => return Ok(Redemption::AlreadyAllowed),
+        Some(ContactStatus::Blocked) => return Ok(Redemption::Blocked),
+        Some(ContactStatus::Pending) | None => {}
+    }
+
+    let invite: Option<UsedInvite> = sqlx::query_as(
+        "UPDATE invites SET uses = uses + 1 \
+         WHERE code = $1 AND revoked_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > NOW()) \
+         AND (max_uses IS NULL OR uses < max_uses) \
+         RETURNING code, created_by",
+    )
+    .bind(code.to_ascii_uppercase())
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(UsedInvite {
+        code,
+        created_by: invited_by,
+    }) = invite
+    else {
+        return Ok(Redemption::Invalid);
+    };
+
+    sqlx::query(
+        "INSERT INTO contacts (sender, status, invite_code, invited_by) \
+         VALUES ($1, 'allowed', $2, $3) \
+         ON CONFLICT (sender) DO UPDATE SET status = 'allowed', \
+         invite_code = EXCLUDED.invite_code, invited_by = EXCLUDED.invited_by, updated_at = NOW()",
+    )
+    .bind(sender)
+    .bind(&code)
+    .bind(&invited_by)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    info!(
+        "🎟️  {} joined with invite {} from {}",
+        sender,
+        code,
+        invited_by.as_deref().unwrap_or("an admin")
+    );
+    Ok(Redemption::Joined { invited_by })
+}
+
+/// Issue a new invite code
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If `max_uses` is below 1 or `expires_in_hours`
+///   is outside 1-8760
+///
+/// # Example
+///
+/// ```json
+/// POST /invites
+/// {
+///   "created_by": "Chief of Staff",
+///   "max_uses": 3,
+///   "expires_in_hours": 48
+/// }
+///
+/// Response:
+/// {
+///   "code": "K7QH2MXP",
+///   "created_by": "Chief of Staff",
+///   "max_uses": 3,
+///   "uses": 0,
+///   "expires_at": "2025-01-03T12:00:00Z",
+///   "revoked_at": null,
+///   "created_at": "2025-01-01T12:00:00Z"
+/// }
+/// ```
+pub async fn create_invite(
+    State(state): State<AppState>,
+    Json(payload): Json<CreateInvite>,
+) -> AppResult<Json<Invite>> {
+    let max_uses = payload.max_uses.unwrap_or(1);
+    if max_uses < 1 {
+        return Err(AppError::validation(
+            "max_uses",
+            "An invite must allow at least one use",
+        ));
+    }
+    let expires_in_hours = payload.expires_in_hours.unwrap_or(DEFAULT_EXPIRY_HOURS);
+    if !(1..=8760).contains(&expires_in_hours) {
+        return Err(AppError::validation(
+            "expires_in_hours",
+            "Invites must expire within 1 to 8760 hours",
+        ));
+    }
+
+    let invite: Invite = sqlx::query_as(
+        "INSERT INTO invites (code, created_by, max_uses, expires_at) \
+         VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(generate_code())
+    .bind(&payload.created_by)
+    .bind(max_uses)
+    .bind(Utc::now() + Duration::hours(expires_in_hours))
+    .fetch_one(&state.pool)
+    .await?;
+    info!("🎟️  Issued invite {} for {} use(s)", invite.code, max_uses);
+
+    Ok(Json(invite))
+}
+
+/// List invites, newest first
+///
+/// # Example
+///
+/// ```json
+/// GET /invites
+///
+/// Response: an array of invites, as returned by `POST /invites`
+/// ```
+pub async fn list_invites(State(state): State<AppState>) -> AppResult<Json<Vec<Invite>>> {
+    let invites = sqlx::query_as("SELECT * FROM invites ORDER BY created_at DESC, code")
+        .fetch_all(&state.pool)
+        .await?;
+    Ok(Json(invites))
+}
+
+/// Revoke an invite so it can no longer be used
+///
+/// People who already joined with it keep their access.
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If there is no such invite
+///
+/// # Example
+///
+/// ```json
+/// DELETE /invites/K7QH2MXP
+///
+/// Response: the invite, with `revoked_at` set
+/// ```
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+) -> AppResult<Json<Invite>> {
+    let invite: Option<Invite> = sqlx::query_as(
+        "UPDATE invites SET revoked_at = COALESCE(revoked_at, NOW()) WHERE code = $1 RETURNING *",
+    )
+    .bind(code.to_ascii_uppercase())
+    .fetch_optional(&state.pool)
+    .await?;
+    let invite = invite.ok_or_else(|| AppError::not_found(format!("invite {code}")))?;
+    info!("🎟️  Revoked invite {}", invite.code);
+
+    Ok(Json(invite))
+}
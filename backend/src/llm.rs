@@ -1,9 +1,65 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+/// Who said something in a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// One message of a conversation sent to the LLM
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Turn {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
 #[async_trait]
 pub trait LlmClient: Send + Sync + 'static {
     async fn complete(&self, prompt: &str) -> anyhow::Result<String>;
+
+    /// Answer the last of `turns`, following the `system` instructions
+    ///
+    /// `turns` alternate between user and assistant, starting and ending
+    /// with the user. The default folds everything into one prompt for
+    /// [`LlmClient::complete`], with the message to answer last after
+    /// `Message: `.
+    async fn converse(&self, system: &str, turns: &[Turn]) -> anyhow::Result<String> {
+        let Some((last, earlier)) = turns.split_last() else {
+            anyhow::bail!("no message to answer");
+        };
+        let mut prompt = system.to_string();
+        if !earlier.is_empty() {
+            prompt.push_str("\n\nConversation so far:");
+            for turn in earlier {
+                let speaker = match turn.role {
+                    Role::User => "User",
+                    Role::Assistant => "You",
+                };
+                prompt.push_str(&format!("\n{}: {}", speaker, turn.content));
+            }
+        }
+        prompt.push_str(&format!("\n\nMessage: {}", last.content));
+        self.complete(&prompt).await
+    }
 }
 
 pub struct AnthropicClient {
@@ -16,17 +72,13 @@ impl AnthropicClient {
     }
 }
 
-#[derive(Serialize)]
-struct Message<'a> {
-    role: &'static str,
-    content: &'a str,
-}
-
 #[derive(Serialize)]
 struct AnthropicRequest<'a> {
     model: &'static str,
     max_tokens: u32,
-    messages: Vec<Message<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    messages: &'a [Turn],
 }
 
 #[derive(Deserialize)]
@@ -44,13 +96,21 @@ struct AnthropicResponse {
 #[async_trait]
 impl LlmClient for AnthropicClient {
     async fn complete(&self, prompt: &str) -> anyhow::Result<String> {
+        self.send(None, &[Turn::user(prompt)]).await
+    }
+
+    async fn converse(&self, system: &str, turns: &[Turn]) -> anyhow::Result<String> {
+        self.send(Some(system), turns).await
+    }
+}
+
+impl AnthropicClient {
+    async fn send(&self, system: Option<&str>, turns: &[Turn]) -> anyhow::Result<String> {
         let req_body = AnthropicRequest {
             model: "claude-sonnet-4-20250514", // Claude Sonnet 4 (latest)
             max_tokens: 1024,
-            messages: vec![Message {
-                role: "user",
-                content: prompt,
-            }],
+            system,
+            messages: turns,
         };
         
         let resp = reqwest::Client::new()
@@ -1,13 +1,27 @@
+use backend::account::{
+    self, Connector, Registrar, SignalAccount, SignalCliRegistrar, SignalRestRegistrar,
+};
 use backend::error::AppResult;
-use backend::signal::SignalClient;
+use backend::signal::{SignalClient, SimulatedSignalClient, SwitchableSignalClient};
 use backend::{
-    build_app, db, llm::AnthropicClient, signal::SignalCliClient, worker::start_signal_worker,
+    build_app,
+    config::BotConfig,
+    db, expiry,
+    llm::AnthropicClient,
+    outbox,
+    signal::{SignalCliClient, SignalJsonRpcClient, SignalRestClient, SignaldClient},
+    simulator,
+    transcribe::{Transcriber, WhisperCliTranscriber, WhisperHttpTranscriber},
+    worker::start_signal_worker,
     AppState,
 };
 use dotenvy::dotenv;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// The bot's number when simulating Signal without one configured
+const SIMULATED_NUMBER: &str = "+15550000000";
 
 #[tokio::main]
 async fn main() -> AppResult<()> {
@@ -30,22 +44,11 @@ async fn main() -> AppResult<()> {
         }
     };
 
-    let signal_phone = match std::env::var("SIGNAL_PHONE_NUMBER") {
-        Ok(phone) => {
-            info!("✅ SIGNAL_PHONE_NUMBER found: {}", phone);
-            phone
-        }
-        Err(_) => {
-            error!("❌ SIGNAL_PHONE_NUMBER not set - required for Signal integration");
-            std::process::exit(1);
-        }
-    };
-
     let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| {
         error!("DATABASE_URL environment variable is required for PostgreSQL");
         std::process::exit(1);
     });
-    
+
     info!("📁 Database: PostgreSQL connection configured");
 
     let pool = db::init_pool(&database_url).await.map_err(|e| {
@@ -57,28 +60,162 @@ async fn main() -> AppResult<()> {
     let llm_client = Arc::new(AnthropicClient::new(api_key));
     info!("✅ LLM client initialized");
 
-    let signal_client = Arc::new(SignalCliClient::new(signal_phone.clone()));
-    info!("✅ Signal client initialized");
-
-    // Test Signal CLI availability at startup
-    info!("🔍 Testing Signal CLI availability...");
-    match signal_client
-        .send_message(&signal_phone, "Startup test - ignore")
-        .await
-    {
-        Ok(_) => info!("✅ Signal CLI test successful"),
+    // The active number is stored in the database so it can be changed from
+    // the web UI; SIGNAL_PHONE_NUMBER seeds it on first start
+    let signal_phone = match account::active_number(&pool).await {
+        Ok(Some(phone)) => {
+            info!("✅ Signal account: {}", phone);
+            Some(phone)
+        }
+        Ok(None) => match std::env::var("SIGNAL_PHONE_NUMBER") {
+            Ok(phone) => {
+                info!("✅ SIGNAL_PHONE_NUMBER found: {}", phone);
+                if let Err(e) = account::activate(&pool, &phone).await {
+                    error!("❌ Failed to store Signal account: {}", e);
+                }
+                Some(phone)
+            }
+            Err(_) => {
+                warn!("⚠️  No Signal number yet - register one via POST /api/signal/update-number");
+                None
+            }
+        },
         Err(e) => {
-            error!("❌ Signal CLI test failed: {}", e);
-            error!("💡 Make sure signal-cli is installed and registered");
-            error!("💡 Try: brew install signal-cli (macOS) or apt install signal-cli (Linux)");
-            error!("💡 Register with: signal-cli -a {} register", &signal_phone);
+            error!("Failed to load Signal account: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // SIGNAL_TRANSPORT selects how we talk to Signal: "cli" spawns signal-cli
+    // per call, "jsonrpc" keeps one signal-cli daemon running, "signald" uses
+    // the signald socket and "rest" talks to a signal-cli-rest-api container.
+    // "simulated" is for development: nothing reaches Signal, and the
+    // /dev/signal endpoints play the part of everyone messaging the bot.
+    let transport = std::env::var("SIGNAL_TRANSPORT").unwrap_or_else(|_| "cli".to_string());
+    let signal_phone = match transport.as_str() {
+        "simulated" => signal_phone.or_else(|| Some(SIMULATED_NUMBER.to_string())),
+        _ => signal_phone,
+    };
+    let mut simulated = None;
+    // Numbers are registered and linked with signal-cli, run locally or by
+    // the REST container; signald and the simulator keep their own account
+    let mut registrar: Option<Arc<dyn Registrar>> = match transport.as_str() {
+        "cli" | "jsonrpc" => Some(Arc::new(SignalCliRegistrar::new())),
+        _ => None,
+    };
+    let connect: Connector = match transport.as_str() {
+        "cli" => Box::new(|phone: &str| Arc::new(SignalCliClient::new(phone.to_string()))),
+        "jsonrpc" => Box::new(|phone: &str| Arc::new(SignalJsonRpcClient::new(phone.to_string()))),
+        "signald" => {
+            let socket_path = std::env::var("SIGNALD_SOCKET")
+                .unwrap_or_else(|_| "/var/run/signald/signald.sock".to_string());
+            info!("🔌 signald socket: {}", socket_path);
+            Box::new(move |phone: &str| {
+                Arc::new(SignaldClient::new(socket_path.clone(), phone.to_string()))
+            })
+        }
+        "rest" => {
+            let base_url = std::env::var("SIGNAL_REST_URL").unwrap_or_else(|_| {
+                error!("❌ SIGNAL_REST_URL is required when SIGNAL_TRANSPORT=rest");
+                std::process::exit(1);
+            });
+            info!("🌐 signal-cli-rest-api: {}", base_url);
+            registrar = Some(Arc::new(SignalRestRegistrar::new(base_url.clone())));
+            let websocket = std::env::var("SIGNAL_REST_WEBSOCKET").is_ok_and(|v| v == "true");
+            Box::new(move |phone: &str| -> Arc<dyn SignalClient> {
+                let client = SignalRestClient::new(base_url.clone(), phone.to_string());
+                if websocket {
+                    Arc::new(client.with_websocket())
+                } else {
+                    Arc::new(client)
+                }
+            })
+        }
+        "simulated" => {
+            let client = Arc::new(SimulatedSignalClient::new(
+                signal_phone.clone().unwrap_or_default(),
+            ));
+            warn!("🧪 Simulating Signal - no messages reach real phones");
+            simulated = Some(client.clone());
+            // Changing the number keeps the same simulator, which the
+            // /dev/signal endpoints hold on to
+            Box::new(move |_phone: &str| -> Arc<dyn SignalClient> { client.clone() })
+        }
+        other => {
+            error!(
+                "❌ Unknown SIGNAL_TRANSPORT '{}' (expected 'cli', 'jsonrpc', 'signald', 'rest' or 'simulated')",
+                other
+            );
+            std::process::exit(1);
+        }
+    };
+    let signal_client = Arc::new(SwitchableSignalClient::new(
+        signal_phone.as_deref().map(&connect),
+    ));
+    info!("✅ Signal client initialized (transport: {})", transport);
+
+    // Test Signal CLI availability at startup. Checking the registration
+    // rather than sending a test message keeps our own inbox empty, so the
+    // worker has nothing of ours to answer.
+    let uses_local_signal_cli = matches!(transport.as_str(), "cli" | "jsonrpc");
+    if let (Some(signal_phone), Some(registrar)) = (
+        signal_phone.as_deref().filter(|_| uses_local_signal_cli),
+        &registrar,
+    ) {
+        info!("🔍 Testing Signal CLI availability...");
+        match registrar.is_registered(signal_phone).await {
+            Ok(true) => info!("✅ Signal CLI test successful"),
+            Ok(false) => {
+                warn!("⚠️  {} is not registered with signal-cli", signal_phone);
+                warn!("💡 Register with: signal-cli -a {} register", signal_phone);
+            }
+            Err(e) => {
+                error!("❌ Signal CLI test failed: {}", e);
+                error!("💡 Make sure signal-cli is installed and registered");
+                error!("💡 Try: brew install signal-cli (macOS) or apt install signal-cli (Linux)");
+                error!("💡 Register with: signal-cli -a {} register", signal_phone);
+            }
         }
     }
 
+    let account = match registrar {
+        Some(registrar) => SignalAccount::new(registrar, signal_client.clone(), connect),
+        None => SignalAccount::without_registrar(signal_client.clone(), connect),
+    };
+
+    let config = BotConfig::from_env()?;
+    info!("✅ Group reply policy: {:?}", config.group_reply_policy);
+    if config.account_uuid.is_none() {
+        warn!("⚠️  SIGNAL_ACCOUNT_UUID is not set; messages from our own account that carry only its uuid will not be recognised");
+    }
+
+    // Voice notes are transcribed by a whisper server (WHISPER_URL) or a
+    // local whisper command (WHISPER_COMMAND, audio file path appended)
+    let transcriber: Option<Arc<dyn Transcriber>> = if let Ok(url) = std::env::var("WHISPER_URL") {
+        info!("✅ Voice notes transcribed via whisper server: {}", url);
+        Some(Arc::new(WhisperHttpTranscriber::new(url)))
+    } else if let Ok(command) = std::env::var("WHISPER_COMMAND") {
+        let mut parts = command.split_whitespace().map(str::to_string);
+        match parts.next() {
+            Some(program) => {
+                info!("✅ Voice notes transcribed via command: {}", command);
+                let args = parts.collect();
+                Some(Arc::new(WhisperCliTranscriber::new(program, args)))
+            }
+            None => None,
+        }
+    } else {
+        info!("💡 No WHISPER_URL or WHISPER_COMMAND set - voice notes will not be transcribed");
+        None
+    };
+
     let state = AppState {
         pool,
         llm: llm_client,
         signal: signal_client,
+        config,
+        transcriber,
+        account: Some(Arc::new(account)),
     };
 
     // Start background Signal worker
@@ -87,8 +224,16 @@ async fn main() -> AppResult<()> {
     tokio::spawn(async move {
         start_signal_worker(worker_state).await;
     });
+    tokio::spawn(expiry::start_purge_task(state.pool.clone()));
+    tokio::spawn(outbox::start_outbox_sender(state.clone()));
 
-    let app = build_app(state);
+    let app = match simulated {
+        Some(simulated) => {
+            info!("🧪 Simulator endpoints mounted under /dev/signal");
+            build_app(state).merge(simulator::router(simulated))
+        }
+        None => build_app(state),
+    };
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     info!("🌐 Server listening on {}", addr);
@@ -98,9 +243,9 @@ async fn main() -> AppResult<()> {
         backend::error::AppError::internal(format!("Failed to bind to address {addr}: {e}"))
     })?;
 
-    axum::serve(listener, app).await.map_err(|e| {
-        backend::error::AppError::internal(format!("Server error: {e}"))
-    })?;
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| backend::error::AppError::internal(format!("Server error: {e}")))?;
 
     Ok(())
 }
@@ -0,0 +1,216 @@
+//! Keeping stored messages in step with Signal
+//!
+//! Signal lets people edit or delete a message after sending it. Stored
+//! questions are looked up by sender and sent timestamp so an edit can
+//! replace the text and a deletion can blank it, leaving a tombstone row so
+//! answers still point at something. Operators can also delete one of the
+//! bot's replies for everyone through `DELETE /messages/:id`.
+
+use crate::error::{AppError, AppResult};
+use crate::signal::Recipient;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::info;
+
+/// A bot reply that can still be deleted in Signal
+#[derive(Debug, FromRow)]
+pub struct StoredAnswer {
+    pub id: String,
+    /// Sent timestamp, `None` when the transport did not report one
+    pub signal_timestamp: Option<i64>,
+    /// Sent timestamps of the parts after the first, for long replies
+    pub part_timestamps: Vec<i64>,
+}
+
+impl StoredAnswer {
+    /// Sent timestamps of every part of the reply, in order
+    pub fn sent_timestamps(&self) -> impl Iterator<Item = u64> + '_ {
+        self.signal_timestamp
+            .iter()
+            .chain(&self.part_timestamps)
+            .map(|&timestamp| timestamp as u64)
+    }
+}
+
+/// Replace the text of `sender`'s stored question sent at `timestamp`
+///
+/// The question then expires `expires_in_seconds` from now, the timer the
+/// edit was sent with. Returns the id of the updated row, or `None` if the
+/// question was never stored.
+pub async fn apply_edit(
+    pool: &PgPool,
+    sender: &str,
+    timestamp: u64,
+    content: &str,
+    expires_in_seconds: Option<u32>,
+) -> anyhow::Result<Option<String>> {
+    let id = sqlx::query_scalar(
+        "UPDATE messages SET content = $1, edited_at = NOW(), \
+         expires_at = NOW() + $4::INT * INTERVAL '1 second' \
+         WHERE role = 'user' AND sender = $2 AND signal_timestamp = $3 AND deleted_at IS NULL \
+         RETURNING id",
+    )
+    .bind(content)
+    .bind(sender)
+    .bind(timestamp as i64)
+    .bind(expires_in_seconds.map(|seconds| seconds as i32))
+    .fetch_optional(pool)
+    .await?;
+    Ok(id)
+}
+
+/// Blank `sender`'s stored question sent at `timestamp` after they deleted it
+///
+/// Attachment metadata goes with it. Returns whether a stored question was
+/// found.
+pub async fn tombstone_question(
+    pool: &PgPool,
+    sender: &str,
+    timestamp: u64,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    let id: Option<String> = sqlx::query_scalar(
+        "UPDATE messages SET content = '', deleted_at = NOW() \
+         WHERE role = 'user' AND sender = $1 AND signal_timestamp = $2 AND deleted_at IS NULL \
+         RETURNING id",
+    )
+    .bind(sender)
+    .bind(timestamp as i64)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(id) = &id {
+        sqlx::query("DELETE FROM attachments WHERE message_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(id.is_some())
+}
+
+/// Blank a stored message and mark it deleted
+pub async fn tombstone(pool: &PgPool, id: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE messages SET content = '', deleted_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Replies still on record for `sender`'s question sent at `timestamp`
+pub async fn answers_to_question(
+    pool: &PgPool,
+    sender: &str,
+    timestamp: u64,
+) -> anyhow::Result<Vec<StoredAnswer>> {
+    let answers = sqlx::query_as(
+        "SELECT a.id, a.signal_timestamp, a.part_timestamps FROM messages a \
+         JOIN messages q ON q.id = a.reply_to \
+         WHERE q.role = 'user' AND q.sender = $1 AND q.signal_timestamp = $2 \
+         AND a.deleted_at IS NULL",
+    )
+    .bind(sender)
+    .bind(timestamp as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(answers)
+}
+
+/// Where a stored reply was sent, worked out from the question it answers
+#[derive(FromRow)]
+struct SentReply {
+    #[sqlx(flatten)]
+    answer: StoredAnswer,
+    sender: Option<String>,
+    group_id: Option<String>,
+}
+
+/// Response for a remote delete
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteDeleteResponse {
+    /// Id of the deleted reply
+    pub message_id: String,
+    /// Whether the reply was deleted for everyone in Signal
+    pub deleted: bool,
+}
+
+/// Delete one of the bot's replies for everyone in the conversation
+///
+/// Sends a Signal remote delete for each part of the reply, then blanks the
+/// stored row.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing the database and Signal client
+/// * `id` - Id of the assistant message to delete
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If there is no such reply, or it was already deleted
+/// * `AppError::Validation` - If the reply was not sent over Signal
+/// * `AppError::Signal` - If Signal rejects the remote delete
+///
+/// # Example
+///
+/// ```json
+/// DELETE /messages/6f1c2a4e-...
+///
+/// Response:
+/// {
+///   "message_id": "6f1c2a4e-...",
+///   "deleted": true
+/// }
+/// ```
+pub async fn remote_delete_message(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<RemoteDeleteResponse>> {
+    let reply: Option<SentReply> = sqlx::query_as(
+        "SELECT a.id, a.signal_timestamp, a.part_timestamps, q.sender, q.group_id FROM messages a \
+         LEFT JOIN messages q ON q.id = a.reply_to \
+         WHERE a.id = $1 AND a.role = 'assistant' AND a.deleted_at IS NULL",
+    )
+    .bind(&id)
+    .fetch_optional(&state.pool)
+    .await?;
+    let reply = reply.ok_or_else(|| AppError::not_found(format!("message {id}")))?;
+
+    let recipient = match (reply.group_id, reply.sender) {
+        (Some(group_id), _) => Recipient::Group(group_id),
+        (None, Some(sender)) => Recipient::Direct(sender),
+        (None, None) => {
+            return Err(AppError::validation(
+                "id",
+                "Message was not sent over Signal",
+            ))
+        }
+    };
+    if reply.answer.signal_timestamp.is_none() {
+        return Err(AppError::validation(
+            "id",
+            "Signal did not report when this message was sent",
+        ));
+    }
+
+    // Long replies went out in parts, each deleted separately
+    for timestamp in reply.answer.sent_timestamps() {
+        state
+            .signal
+            .remote_delete(&recipient, timestamp)
+            .await
+            .map_err(|e| AppError::signal(e.to_string()))?;
+    }
+    tombstone(&state.pool, &id)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    info!("🗑️  Deleted reply {} for everyone", id);
+
+    Ok(Json(RemoteDeleteResponse {
+        message_id: id,
+        deleted: true,
+    }))
+}
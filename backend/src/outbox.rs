@@ -0,0 +1,486 @@
+//! Outgoing messages that must not be lost
+//!
+//! Replies and `/signal/send` messages are written to the outbox before the
+//! first attempt to send them. If Signal is unreachable or rejects the send,
+//! [`start_outbox_sender`] tries again with a delay that doubles each time,
+//! up to [`BotConfig::outbox_max_attempts`] attempts. Messages that still
+//! fail are dead-lettered: they stay in the outbox with their last error,
+//! where an admin can list them through `/outbox` and retry them.
+//!
+//! [`BotConfig::outbox_max_attempts`]: crate::config::BotConfig::outbox_max_attempts
+
+use crate::error::{AppError, AppResult};
+use crate::identities::{self, ChangeSource, IdentityStatus};
+use crate::ratelimit;
+use crate::signal::format::{self, PartsSent};
+use crate::signal::{OutgoingMessage, Quote, RateLimited, Recipient, UntrustedIdentity};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// How often the sender looks for messages due another attempt
+const SEND_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Delay before the first retry; each later retry waits twice as long
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest delay between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How long a message being sent is kept from other senders. If the process
+/// dies mid-send, the message is picked up again after this.
+const CLAIM_SECONDS: i32 = 5 * 60;
+
+/// Most messages sent per round of the sender
+const BATCH_SIZE: i64 = 20;
+
+/// Where an outgoing message stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Not delivered yet; another attempt is due at `next_attempt_at`
+    Pending,
+    /// Delivered
+    Sent,
+    /// Gave up after too many failed attempts
+    Dead,
+}
+
+/// An outgoing message and its delivery attempts
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OutboxEntry {
+    pub id: String,
+    /// Number or ACI uuid, or the group id when `is_group` is set
+    pub recipient: String,
+    pub is_group: bool,
+    /// Markdown text, formatted and split when it is sent
+    pub content: String,
+    pub quote_timestamp: Option<i64>,
+    pub quote_author: Option<String>,
+    pub quote_text: Option<String>,
+    /// The stored reply this message delivers
+    pub message_id: Option<String>,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    /// Parts of a split message delivered so far
+    pub parts_sent: i32,
+    /// Timestamps Signal gave the delivered parts, `NULL` where it gave none
+    pub sent_timestamps: Vec<Option<i64>>,
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl OutboxEntry {
+    /// The message to send
+    pub fn message(&self) -> OutgoingMessage {
+        let recipient = if self.is_group {
+            Recipient::Group(self.recipient.clone())
+        } else {
+            Recipient::Direct(self.recipient.clone())
+        };
+        let message = OutgoingMessage::new(recipient, &self.content);
+        match (self.quote_timestamp, &self.quote_author) {
+            (Some(timestamp), Some(author)) => message.with_quote(Quote {
+                timestamp: timestamp as u64,
+                author: author.clone(),
+                text: self.quote_text.clone().unwrap_or_default(),
+            }),
+            _ => message,
+        }
+    }
+
+    /// The parts delivered by earlier attempts
+    pub fn parts_sent(&self) -> PartsSent {
+        let mut timestamps: Vec<Option<u64>> = self
+            .sent_timestamps
+            .iter()
+            .map(|timestamp| timestamp.map(|t| t as u64))
+            .collect();
+        timestamps.resize(self.parts_sent as usize, None);
+        PartsSent { timestamps }
+    }
+}
+
+/// How long to wait before the attempt after `attempts` failed ones
+pub fn retry_delay(attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (RETRY_BASE_DELAY * 2u32.pow(doublings)).min(MAX_RETRY_DELAY)
+}
+
+/// Put `message` in the outbox, claimed for the caller to send right away
+///
+/// `message_id` is the stored reply the message delivers, if any; the
+/// timestamps Signal gives it are recorded there once it is sent.
+pub async fn enqueue(
+    pool: &PgPool,
+    message: &OutgoingMessage,
+    message_id: Option<&str>,
+) -> anyhow::Result<OutboxEntry> {
+    let (recipient, is_group) = match &message.recipient {
+        Recipient::Direct(to) => (to, false),
+        Recipient::Group(group_id) => (group_id, true),
+    };
+    let entry = sqlx::query_as(
+        "INSERT INTO outbox (id, recipient, is_group, content, quote_timestamp, quote_author, \
+         quote_text, message_id, next_attempt_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW() + $9::INT * INTERVAL '1 second') \
+         RETURNING *",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(recipient)
+    .bind(is_group)
+    .bind(&message.content)
+    .bind(message.quote.as_ref().map(|quote| quote.timestamp as i64))
+    .bind(message.quote.as_ref().map(|quote| &quote.author))
+    .bind(message.quote.as_ref().map(|quote| &quote.text))
+    .bind(message_id)
+    .bind(CLAIM_SECONDS)
+    .fetch_one(pool)
+    .await?;
+    Ok(entry)
+}
+
+/// Make one attempt to send `entry`, recording the outcome
+///
+/// A failed attempt schedules the next one, or dead-letters the message
+/// once [`BotConfig::outbox_max_attempts`] is reached. Messages split into
+/// parts carry on from the first part not yet delivered. Messages held back
+/// by a rate limit are not attempted, and neither are those Signal rate
+/// limits: they wait for sending to resume without using up their attempts.
+///
+/// [`BotConfig::outbox_max_attempts`]: crate::config::BotConfig::outbox_max_attempts
+pub async fn deliver(state: &AppState, entry: &OutboxEntry) -> anyhow::Result<Vec<Option<u64>>> {
+    if let Some(until) = ratelimit::held_until(&state.pool, &state.config, &entry.recipient).await?
+    {
+        sqlx::query("UPDATE outbox SET next_attempt_at = $1 WHERE id = $2")
+            .bind(until)
+            .bind(&entry.id)
+            .execute(&state.pool)
+            .await?;
+        anyhow::bail!(
+            "sending to {} is held back by rate limits until {}",
+            entry.recipient,
+            until
+        );
+    }
+
+    let attempts = entry.attempts + 1;
+    let mut sent = entry.parts_sent();
+    let result = send(state, &entry.message(), &mut sent).await;
+    if sent.count() > entry.parts_sent as usize {
+        record_parts_sent(state, entry, &sent).await?;
+    }
+    let error = match result {
+        Ok(()) => {
+            sqlx::query(
+                "UPDATE outbox SET status = 'sent', attempts = $1, last_error = NULL, \
+                 sent_at = NOW() WHERE id = $2",
+            )
+            .bind(attempts)
+            .bind(&entry.id)
+            .execute(&state.pool)
+            .await?;
+            return Ok(sent.timestamps);
+        }
+        Err(e) => e,
+    };
+
+    if let Some(limited) = error.downcast_ref::<RateLimited>() {
+        ratelimit::pause(&state.pool, limited, &error.to_string()).await?;
+        // Due again right away, so it goes out as soon as the pause ends
+        sqlx::query("UPDATE outbox SET last_error = $1, next_attempt_at = NOW() WHERE id = $2")
+            .bind(error.to_string())
+            .bind(&entry.id)
+            .execute(&state.pool)
+            .await?;
+        return Err(error);
+    }
+
+    if attempts as u32 >= state.config.outbox_max_attempts {
+        error!(
+            "☠️  Giving up on message {} to {} after {} attempts: {}",
+            entry.id, entry.recipient, attempts, error
+        );
+        sqlx::query(
+            "UPDATE outbox SET status = 'dead', attempts = $1, last_error = $2 WHERE id = $3",
+        )
+        .bind(attempts)
+        .bind(error.to_string())
+        .bind(&entry.id)
+        .execute(&state.pool)
+        .await?;
+    } else {
+        let delay = retry_delay(attempts as u32);
+        warn!(
+            "📮 Message {} to {} failed, retrying in {}s: {}",
+            entry.id,
+            entry.recipient,
+            delay.as_secs(),
+            error
+        );
+        sqlx::query(
+            "UPDATE outbox SET attempts = $1, last_error = $2, \
+             next_attempt_at = NOW() + $3::INT * INTERVAL '1 second' WHERE id = $4",
+        )
+        .bind(attempts)
+        .bind(error.to_string())
+        .bind(delay.as_secs() as i32)
+        .bind(&entry.id)
+        .execute(&state.pool)
+        .await?;
+    }
+    Err(error)
+}
+
+/// Remember which parts of `entry` went out, so a retry does not send them
+/// again
+async fn record_parts_sent(
+    state: &AppState,
+    entry: &OutboxEntry,
+    sent: &PartsSent,
+) -> anyhow::Result<()> {
+    let timestamps: Vec<Option<i64>> = sent
+        .timestamps
+        .iter()
+        .map(|timestamp| timestamp.map(|t| t as i64))
+        .collect();
+    sqlx::query("UPDATE outbox SET parts_sent = $1, sent_timestamps = $2 WHERE id = $3")
+        .bind(sent.count() as i32)
+        .bind(&timestamps)
+        .bind(&entry.id)
+        .execute(&state.pool)
+        .await?;
+    // Reactions to the reply will refer to it by these timestamps
+    if let Some(message_id) = &entry.message_id {
+        if let Err(e) = record_signal_timestamps(&state.pool, message_id, &sent.timestamps).await {
+            warn!("⚠️  Failed to record sent timestamp: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Queue `message` and make the first attempt to send it right away
+///
+/// `message_id` is the stored reply the message delivers, if any. Returns
+/// the sent timestamps when the attempt succeeded; otherwise the message is
+/// retried later. A message that cannot be queued is not sent, as nothing
+/// would count it toward the rate limits.
+pub async fn submit(
+    state: &AppState,
+    message: &OutgoingMessage,
+    message_id: Option<&str>,
+) -> anyhow::Result<Vec<Option<u64>>> {
+    let entry = enqueue(&state.pool, message, message_id).await?;
+    deliver(state, &entry).await
+}
+
+/// Send the parts of a formatted message not yet in `sent`, retrying once
+/// if the recipient's new identity key gets trusted
+pub(crate) async fn send(
+    state: &AppState,
+    message: &OutgoingMessage,
+    sent: &mut PartsSent,
+) -> anyhow::Result<()> {
+    let max_chars = state.config.max_message_chars;
+    let error = match format::send_formatted(state.signal.as_ref(), message, max_chars, sent).await
+    {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    let Some(identity) = error.downcast_ref::<UntrustedIdentity>() else {
+        return Err(error);
+    };
+
+    let change = identities::identity_changed(
+        &state.pool,
+        state.signal.as_ref(),
+        state.config.identity_trust,
+        &identity.number,
+        ChangeSource::Send,
+    )
+    .await;
+    match change {
+        Ok(change) if change.status == IdentityStatus::Trusted => {
+            format::send_formatted(state.signal.as_ref(), message, max_chars, sent).await
+        }
+        Ok(_) => Err(error),
+        Err(e) => {
+            warn!("⚠️  Failed to handle identity change: {}", e);
+            Err(error)
+        }
+    }
+}
+
+/// Remember the timestamps Signal assigned to each part of a sent reply
+///
+/// The first part's timestamp, which may be missing, identifies the reply;
+/// the later parts only keep the timestamps that were reported.
+async fn record_signal_timestamps(
+    pool: &PgPool,
+    message_id: &str,
+    timestamps: &[Option<u64>],
+) -> anyhow::Result<()> {
+    let Some((first, rest)) = timestamps.split_first() else {
+        return Ok(());
+    };
+    let first = first.map(|t| t as i64);
+    let rest: Vec<i64> = rest.iter().flatten().map(|&t| t as i64).collect();
+    sqlx::query("UPDATE messages SET signal_timestamp = $1, part_timestamps = $2 WHERE id = $3")
+        .bind(first)
+        .bind(rest)
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Retry every pending message whose next attempt is due
+///
+/// Returns how many were delivered.
+pub async fn deliver_due(state: &AppState) -> anyhow::Result<usize> {
+    let mut due: Vec<OutboxEntry> = sqlx::query_as(
+        "UPDATE outbox SET next_attempt_at = NOW() + $1::INT * INTERVAL '1 second' \
+         WHERE id IN (SELECT id FROM outbox WHERE status = 'pending' AND next_attempt_at <= NOW() \
+         ORDER BY next_attempt_at LIMIT $2 FOR UPDATE SKIP LOCKED) RETURNING *",
+    )
+    .bind(CLAIM_SECONDS)
+    .bind(BATCH_SIZE)
+    .fetch_all(&state.pool)
+    .await?;
+    due.sort_by_key(|entry| entry.created_at);
+
+    let mut delivered = 0;
+    for entry in &due {
+        if deliver(state, entry).await.is_ok() {
+            info!("📮 Delivered message {} to {}", entry.id, entry.recipient);
+            delivered += 1;
+        }
+    }
+    Ok(delivered)
+}
+
+/// Keep retrying failed messages until they are delivered or dead-lettered
+pub async fn start_outbox_sender(state: AppState) {
+    info!("📮 Outbox sender started");
+
+    loop {
+        match deliver_due(&state).await {
+            Ok(0) => debug!("📮 No messages due for another attempt"),
+            Ok(delivered) => info!("📮 Delivered {} queued messages", delivered),
+            Err(e) => error!("❌ Failed to deliver queued messages: {}", e),
+        }
+        sleep(SEND_INTERVAL).await;
+    }
+}
+
+/// Query parameters for listing the outbox
+#[derive(Debug, Deserialize)]
+pub struct OutboxQuery {
+    /// Only list messages with this status
+    pub status: Option<OutboxStatus>,
+}
+
+/// List outgoing messages, newest first
+///
+/// # Example
+///
+/// ```json
+/// GET /outbox?status=dead
+///
+/// Response:
+/// [
+///   {
+///     "id": "5d1c...",
+///     "recipient": "+15550001111",
+///     "is_group": false,
+///     "content": "The committee will likely ask about shipbuilding...",
+///     "quote_timestamp": 1700000000000,
+///     "quote_author": "+15550001111",
+///     "quote_text": "What will they ask?",
+///     "message_id": "9a0f...",
+///     "status": "dead",
+///     "attempts": 8,
+///     "last_error": "send failed: Unregistered user",
+///     "next_attempt_at": "2025-01-01T13:00:00Z",
+///     "created_at": "2025-01-01T12:00:00Z",
+///     "sent_at": null
+///   }
+/// ]
+/// ```
+pub async fn list_outbox(
+    State(state): State<AppState>,
+    Query(query): Query<OutboxQuery>,
+) -> AppResult<Json<Vec<OutboxEntry>>> {
+    let entries = sqlx::query_as(
+        "SELECT * FROM outbox WHERE $1::TEXT IS NULL OR status = $1 \
+         ORDER BY created_at DESC, id",
+    )
+    .bind(query.status)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(Json(entries))
+}
+
+/// Try to send an undelivered message again right away
+///
+/// The attempt count starts over, so a dead-lettered message gets the full
+/// number of retries again if this attempt fails too.
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If there is no such message
+/// * `AppError::Validation` - If the message was already delivered
+///
+/// # Example
+///
+/// ```json
+/// POST /outbox/5d1c.../retry
+///
+/// Response: the message, with `status` set to `sent` if this attempt
+/// delivered it
+/// ```
+pub async fn retry_outbox(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<OutboxEntry>> {
+    let entry: Option<OutboxEntry> = sqlx::query_as(
+        "UPDATE outbox SET status = 'pending', attempts = 0, \
+         next_attempt_at = NOW() + $1::INT * INTERVAL '1 second' \
+         WHERE id = $2 AND status <> 'sent' RETURNING *",
+    )
+    .bind(CLAIM_SECONDS)
+    .bind(&id)
+    .fetch_optional(&state.pool)
+    .await?;
+    let Some(entry) = entry else {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM outbox WHERE id = $1)")
+            .bind(&id)
+            .fetch_one(&state.pool)
+            .await?;
+        return Err(if exists {
+            AppError::validation("id", "This message has already been delivered")
+        } else {
+            AppError::not_found(format!("outbox message {id}"))
+        });
+    };
+
+    info!("📮 Retrying message {} to {}", entry.id, entry.recipient);
+    // A failure is recorded on the message, which is returned either way
+    let _ = deliver(&state, &entry).await;
+    let entry = sqlx::query_as("SELECT * FROM outbox WHERE id = $1")
+        .bind(&id)
+        .fetch_one(&state.pool)
+        .await?;
+    Ok(Json(entry))
+}
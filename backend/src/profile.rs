@@ -0,0 +1,226 @@
+//! The profile the bot's Signal account shows
+//!
+//! Without a profile the bot shows up as a bare phone number. `PUT
+//! /api/signal/profile` sets its name, about text, emoji and avatar in Signal
+//! and keeps them in the database, because a freshly registered number starts
+//! with an empty profile: [`reapply`] sets the stored one again once a new
+//! number is verified.
+
+use crate::error::{AppError, AppResult};
+use crate::signal::{Profile, SignalClient};
+use crate::AppState;
+use axum::extract::State;
+use axum::Json;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::info;
+
+/// Longest profile name Signal accepts
+const MAX_NAME_CHARS: usize = 26;
+
+/// Longest about text Signal accepts
+const MAX_ABOUT_CHARS: usize = 140;
+
+/// Largest avatar accepted, in bytes
+const MAX_AVATAR_BYTES: usize = 5 * 1024 * 1024;
+
+/// Request to change the bot's profile
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfileRequest {
+    pub name: String,
+    #[serde(default)]
+    pub about: Option<String>,
+    #[serde(default)]
+    pub about_emoji: Option<String>,
+    /// Base64 JPEG or PNG, optionally as a `data:` URL; leave out to keep the
+    /// current avatar
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+/// The bot's profile as shown to the operator
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileResponse {
+    pub name: String,
+    pub about: Option<String>,
+    pub about_emoji: Option<String>,
+    pub has_avatar: bool,
+}
+
+impl From<Profile> for ProfileResponse {
+    fn from(profile: Profile) -> Self {
+        Self {
+            name: profile.name,
+            about: profile.about,
+            about_emoji: profile.about_emoji,
+            has_avatar: profile.avatar.is_some(),
+        }
+    }
+}
+
+/// The stored profile, if one was ever set
+pub async fn stored_profile(pool: &PgPool) -> anyhow::Result<Option<Profile>> {
+    let profile =
+        sqlx::query_as("SELECT name, about, about_emoji, avatar FROM signal_profile WHERE id")
+            .fetch_optional(pool)
+            .await?;
+    Ok(profile)
+}
+
+/// Store `profile`, keeping the stored avatar if it has none
+async fn save(pool: &PgPool, profile: &Profile) -> anyhow::Result<Profile> {
+    let saved = sqlx::query_as(
+        "INSERT INTO signal_profile (id, name, about, about_emoji, avatar) \
+         VALUES (TRUE, $1, $2, $3, $4) \
+         ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, about = EXCLUDED.about, \
+         about_emoji = EXCLUDED.about_emoji, \
+         avatar = COALESCE(EXCLUDED.avatar, signal_profile.avatar), updated_at = NOW() \
+         RETURNING name, about, about_emoji, avatar",
+    )
+    .bind(&profile.name)
+    .bind(&profile.about)
+    .bind(&profile.about_emoji)
+    .bind(&profile.avatar)
+    .fetch_one(pool)
+    .await?;
+    Ok(saved)
+}
+
+/// Set the stored profile in Signal again, e.g. after registering a number
+///
+/// Returns whether there was a profile to set.
+pub async fn reapply(pool: &PgPool, signal: &dyn SignalClient) -> anyhow::Result<bool> {
+    let Some(profile) = stored_profile(pool).await? else {
+        return Ok(false);
+    };
+    signal.update_profile(&profile).await?;
+    info!("🪪 Re-applied the Signal profile \"{}\"", profile.name);
+    Ok(true)
+}
+
+/// Trimmed `text`, or `None` if it is blank
+fn non_blank(text: Option<String>) -> Option<String> {
+    text.map(|t| t.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+/// Decode and check an uploaded avatar
+fn decode_avatar(avatar: &str) -> AppResult<Vec<u8>> {
+    let encoded = match avatar.split_once(";base64,") {
+        Some((prefix, data)) if prefix.starts_with("data:") => data,
+        _ => avatar,
+    };
+    let image = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| AppError::validation("avatar", "Avatar must be base64 encoded"))?;
+
+    let is_jpeg = image.starts_with(&[0xFF, 0xD8, 0xFF]);
+    let is_png = image.starts_with(b"\x89PNG\r\n\x1a\n");
+    if !is_jpeg && !is_png {
+        return Err(AppError::validation(
+            "avatar",
+            "Avatar must be a JPEG or PNG image",
+        ));
+    }
+    if image.len() > MAX_AVATAR_BYTES {
+        return Err(AppError::validation(
+            "avatar",
+            "Avatar cannot be larger than 5 MB",
+        ));
+    }
+    Ok(image)
+}
+
+/// Set the bot's Signal profile and remember it
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the name is blank or too long, the about
+///   text is too long, or the avatar is not a JPEG or PNG image
+/// * `AppError::Signal` - If Signal rejects the profile
+///
+/// # Example
+///
+/// ```json
+/// PUT /api/signal/profile
+/// {
+///   "name": "Senator Budd (Prep Bot)",
+///   "about": "Hearing prep for VADM Bradley",
+///   "aboutEmoji": "🏛️",
+///   "avatar": "data:image/png;base64,iVBORw0KGgo..."
+/// }
+///
+/// Response:
+/// {
+///   "name": "Senator Budd (Prep Bot)",
+///   "about": "Hearing prep for VADM Bradley",
+///   "aboutEmoji": "🏛️",
+///   "hasAvatar": true
+/// }
+/// ```
+pub async fn update_profile(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdateProfileRequest>,
+) -> AppResult<Json<ProfileResponse>> {
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AppError::validation(
+            "name",
+            "Name must be between 1 and 26 characters",
+        ));
+    }
+    let about = non_blank(payload.about);
+    if about
+        .as_ref()
+        .is_some_and(|about| about.chars().count() > MAX_ABOUT_CHARS)
+    {
+        return Err(AppError::validation(
+            "about",
+            "About text cannot be longer than 140 characters",
+        ));
+    }
+    let avatar = non_blank(payload.avatar)
+        .map(|avatar| decode_avatar(&avatar))
+        .transpose()?;
+
+    let profile = Profile {
+        name,
+        about,
+        about_emoji: non_blank(payload.about_emoji),
+        avatar,
+    };
+    state
+        .signal
+        .update_profile(&profile)
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    let saved = save(&state.pool, &profile)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    info!("🪪 Signal profile set to \"{}\"", saved.name);
+
+    Ok(Json(saved.into()))
+}
+
+/// The bot's current profile
+///
+/// # Errors
+///
+/// * `AppError::NotFound` - If no profile was ever set
+///
+/// # Example
+///
+/// ```json
+/// GET /api/signal/profile
+///
+/// Response: the profile, as returned by `PUT /api/signal/profile`
+/// ```
+pub async fn get_profile(State(state): State<AppState>) -> AppResult<Json<ProfileResponse>> {
+    let profile = stored_profile(&state.pool)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?
+        .ok_or_else(|| AppError::not_found("Signal profile"))?;
+    Ok(Json(profile.into()))
+}
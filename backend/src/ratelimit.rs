@@ -0,0 +1,235 @@
+//! Staying within Signal's rate limits
+//!
+//! Signal's servers throttle accounts that send too fast, and eventually
+//! want a captcha solved before they accept more. To stay clear of that, the
+//! outbox holds messages back once [`BotConfig::send_limit_per_recipient`] or
+//! [`BotConfig::send_limit_global`] messages went out in the last minute.
+//! When Signal rate limits the bot anyway, all sending pauses until the wait
+//! Signal asked for is over, or until an operator solves the captcha and
+//! submits its token to `POST /api/signal/rate-limit/challenge`.
+//!
+//! [`BotConfig::send_limit_per_recipient`]: crate::config::BotConfig::send_limit_per_recipient
+//! [`BotConfig::send_limit_global`]: crate::config::BotConfig::send_limit_global
+
+use crate::config::BotConfig;
+use crate::error::{AppError, AppResult};
+use crate::signal::RateLimited;
+use crate::AppState;
+use axum::extract::State;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::{info, warn};
+
+/// The window the send limits count messages in
+const WINDOW_SECONDS: i64 = 60;
+
+/// How long to pause when Signal rate limits without saying for how long
+const DEFAULT_PAUSE_SECONDS: i64 = 5 * 60;
+
+/// Sending paused because Signal rate limited the bot
+#[derive(Debug, Clone, FromRow)]
+pub struct SendPause {
+    /// When sending resumes by itself; `None` waits for a solved captcha
+    pub paused_until: Option<DateTime<Utc>>,
+    /// Challenge token a captcha lifts the pause for
+    pub challenge: Option<String>,
+    /// The error Signal gave
+    pub reason: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// The pause currently in effect, if any
+pub async fn active_pause(pool: &PgPool) -> anyhow::Result<Option<SendPause>> {
+    let pause = sqlx::query_as(
+        "SELECT paused_until, challenge, reason, detected_at FROM signal_send_pause \
+         WHERE paused_until IS NULL OR paused_until > NOW()",
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(pause)
+}
+
+/// Pause sending after Signal answered a send with `limited`
+pub async fn pause(pool: &PgPool, limited: &RateLimited, reason: &str) -> anyhow::Result<()> {
+    let seconds = match (limited.retry_after, &limited.challenge) {
+        (Some(seconds), _) => Some(seconds as i64),
+        (None, Some(_)) => None,
+        (None, None) => Some(DEFAULT_PAUSE_SECONDS),
+    };
+    sqlx::query(
+        "INSERT INTO signal_send_pause (id, paused_until, challenge, reason) \
+         VALUES (TRUE, NOW() + $1::BIGINT * INTERVAL '1 second', $2, $3) \
+         ON CONFLICT (id) DO UPDATE SET paused_until = EXCLUDED.paused_until, \
+         challenge = EXCLUDED.challenge, reason = EXCLUDED.reason, detected_at = NOW()",
+    )
+    .bind(seconds)
+    .bind(&limited.challenge)
+    .bind(reason)
+    .execute(pool)
+    .await?;
+
+    match seconds {
+        Some(seconds) => warn!(
+            "🐢 Signal is rate limiting us, pausing sends for {}s",
+            seconds
+        ),
+        None => warn!("🐢 Signal is rate limiting us, pausing sends until a captcha is solved"),
+    }
+    Ok(())
+}
+
+/// When a message to `recipient` may go out, or `None` if it may now
+///
+/// Messages are held back while sending is paused, and while the last
+/// minute already used up the per-recipient or global limit.
+pub async fn held_until(
+    pool: &PgPool,
+    config: &BotConfig,
+    recipient: &str,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let window = chrono::Duration::seconds(WINDOW_SECONDS);
+    if let Some(pause) = active_pause(pool).await? {
+        return Ok(Some(
+            pause.paused_until.unwrap_or_else(|| Utc::now() + window),
+        ));
+    }
+
+    let limits = [
+        (config.send_limit_per_recipient, Some(recipient)),
+        (config.send_limit_global, None),
+    ];
+    for (limit, recipient) in limits {
+        if limit == 0 {
+            continue;
+        }
+        // A slot frees up when the oldest message in the window leaves it
+        let frees_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+            "SELECT MIN(sent_at) + $1::BIGINT * INTERVAL '1 second' FROM outbox \
+             WHERE sent_at > NOW() - $1::BIGINT * INTERVAL '1 second' \
+             AND ($2::TEXT IS NULL OR recipient = $2) HAVING COUNT(*) >= $3",
+        )
+        .bind(WINDOW_SECONDS)
+        .bind(recipient)
+        .bind(limit as i64)
+        .fetch_optional(pool)
+        .await?;
+        if frees_at.is_some() {
+            return Ok(frees_at);
+        }
+    }
+    Ok(None)
+}
+
+/// Whether sending is paused, as shown to the operator
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitStatus {
+    pub paused: bool,
+    /// When sending resumes by itself, if it does
+    pub paused_until: Option<DateTime<Utc>>,
+    /// Whether solving a captcha would resume sending now
+    pub challenge_required: bool,
+    pub reason: Option<String>,
+    pub detected_at: Option<DateTime<Utc>>,
+}
+
+impl From<Option<SendPause>> for RateLimitStatus {
+    fn from(pause: Option<SendPause>) -> Self {
+        match pause {
+            Some(pause) => Self {
+                paused: true,
+                paused_until: pause.paused_until,
+                challenge_required: pause.challenge.is_some(),
+                reason: Some(pause.reason),
+                detected_at: Some(pause.detected_at),
+            },
+            None => Self {
+                paused: false,
+                paused_until: None,
+                challenge_required: false,
+                reason: None,
+                detected_at: None,
+            },
+        }
+    }
+}
+
+/// Request to lift a rate limit challenge
+#[derive(Debug, Deserialize)]
+pub struct SubmitChallengeRequest {
+    /// Token from solving the captcha at
+    /// https://signalcaptchas.org/challenge/generate.html
+    pub captcha: String,
+}
+
+/// Whether sending is paused by a Signal rate limit
+///
+/// # Example
+///
+/// ```json
+/// GET /api/signal/rate-limit
+///
+/// Response:
+/// {
+///   "paused": true,
+///   "pausedUntil": null,
+///   "challengeRequired": true,
+///   "reason": "signal-cli send failed: CAPTCHA proof required for sending to ...",
+///   "detectedAt": "2025-01-01T12:00:00Z"
+/// }
+/// ```
+pub async fn rate_limit_status(State(state): State<AppState>) -> AppResult<Json<RateLimitStatus>> {
+    let pause = active_pause(&state.pool)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?;
+    Ok(Json(pause.into()))
+}
+
+/// Solve Signal's rate limit challenge and resume sending
+///
+/// Held messages are sent from the outbox again within about a minute.
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the captcha is blank or Signal has not
+///   asked for one
+/// * `AppError::Signal` - If Signal does not accept the captcha
+///
+/// # Example
+///
+/// ```json
+/// POST /api/signal/rate-limit/challenge
+/// {
+///   "captcha": "signalcaptcha://signal-hcaptcha.5fad97ac-..."
+/// }
+///
+/// Response: the status, with `paused` set to `false`
+/// ```
+pub async fn submit_challenge(
+    State(state): State<AppState>,
+    Json(payload): Json<SubmitChallengeRequest>,
+) -> AppResult<Json<RateLimitStatus>> {
+    let captcha = payload.captcha.trim();
+    if captcha.is_empty() {
+        return Err(AppError::validation("captcha", "Captcha cannot be empty"));
+    }
+    let challenge = active_pause(&state.pool)
+        .await
+        .map_err(|e| AppError::internal(e.to_string()))?
+        .and_then(|pause| pause.challenge)
+        .ok_or_else(|| AppError::validation("captcha", "Signal has not asked for a captcha"))?;
+
+    state
+        .signal
+        .submit_rate_limit_challenge(&challenge, captcha)
+        .await
+        .map_err(|e| AppError::signal(e.to_string()))?;
+    sqlx::query("DELETE FROM signal_send_pause")
+        .execute(&state.pool)
+        .await?;
+    info!("🧩 Rate limit challenge solved, resuming sends");
+
+    Ok(Json(None.into()))
+}
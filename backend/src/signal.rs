@@ -1,102 +1,828 @@
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
-use std::process::Stdio;
+use std::fmt;
+use std::path::PathBuf;
 use tokio::process::Command;
 use tracing::{debug, error, info, warn};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+mod dispatch;
+pub mod format;
+mod jsonrpc;
+mod rest;
+mod signald;
+mod simulated;
+mod switchable;
+
+pub use format::{StyleRange, TextStyle};
+pub use jsonrpc::SignalJsonRpcClient;
+pub(crate) use rest::error_message as rest_error_message;
+pub use rest::SignalRestClient;
+pub use signald::SignaldClient;
+pub use simulated::{SimulatedMessage, SimulatedSignalClient};
+pub use switchable::SwitchableSignalClient;
+
+/// A Signal user, as identified in envelopes and when sending
+///
+/// The ACI uuid is the stable identity: it survives number changes, and users
+/// with phone number privacy turned on only ever show up by uuid. The number
+/// and username are other ways to address the same user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalAddress {
+    /// ACI uuid
+    pub uuid: Option<String>,
+    /// Phone number, unless the user keeps it private
+    pub number: Option<String>,
+    /// Username, e.g. `budd.01`
+    pub username: Option<String>,
+    /// Profile name the user goes by
+    pub name: Option<String>,
+}
+
+impl SignalAddress {
+    /// Parse how a user is addressed: a phone number with country code, an
+    /// ACI uuid, or a username, optionally prefixed with `u:`
+    pub fn parse(address: &str) -> Option<Self> {
+        let address = address.trim();
+        if let Some(digits) = address.strip_prefix('+') {
+            let valid = digits.len() >= 7 && digits.chars().all(|c| c.is_ascii_digit());
+            return valid.then(|| Self {
+                number: Some(address.to_string()),
+                ..Default::default()
+            });
+        }
+        if let Ok(uuid) = uuid::Uuid::parse_str(address) {
+            return Some(Self {
+                uuid: Some(uuid.to_string()),
+                ..Default::default()
+            });
+        }
+        let username = address.strip_prefix("u:").unwrap_or(address);
+        is_username(username).then(|| Self {
+            username: Some(username.to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// Whether `id` is any of this address's uuid, number or [`Self::id`]
+    pub fn is(&self, id: &str) -> bool {
+        [&self.uuid, &self.number]
+            .into_iter()
+            .flatten()
+            .any(|own| own == id)
+            || self.id() == id
+    }
+
+    /// The key the user is known by, which also addresses them when sending:
+    /// their uuid, else their number, else `u:` and their username
+    pub fn id(&self) -> String {
+        match (&self.uuid, &self.number, &self.username) {
+            (Some(uuid), _, _) => uuid.clone(),
+            (None, Some(number), _) => number.clone(),
+            (None, None, Some(username)) => format!("u:{username}"),
+            (None, None, None) => "unknown".to_string(),
+        }
+    }
+}
+
+/// Signal usernames are a nickname, a dot and at least two digits
+fn is_username(username: &str) -> bool {
+    let Some((nickname, discriminator)) = username.rsplit_once('.') else {
+        return false;
+    };
+    (3..=32).contains(&nickname.len())
+        && nickname.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && nickname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && (2..=9).contains(&discriminator.len())
+        && discriminator.chars().all(|c| c.is_ascii_digit())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SignalMessage {
+    /// The sender's [`SignalAddress::id`]: their uuid when the transport
+    /// reports it, so a conversation survives a number change
     pub from: String,
+    /// Everything known about the sender
+    #[serde(default)]
+    pub source: SignalAddress,
     pub to: String,
     pub content: String,
+    /// Group the message was posted in, `None` for direct messages
+    #[serde(default)]
+    pub group_id: Option<String>,
+    /// Numbers and ACI uuids of everyone @-mentioned in the message
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    /// Files sent with the message (photos, documents, voice notes, ...)
+    #[serde(default)]
+    pub attachments: Vec<SignalAttachment>,
+    /// Sender's timestamp in milliseconds, which is how Signal identifies the
+    /// message in receipts
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Set when the message is an emoji reaction to an earlier message
+    #[serde(default)]
+    pub reaction: Option<SignalReaction>,
+    /// Set when this message is an edit: the sent timestamp of the original
+    /// message it replaces
+    #[serde(default)]
+    pub edited_timestamp: Option<u64>,
+    /// Set when the sender deleted one of their earlier messages: its sent
+    /// timestamp
+    #[serde(default)]
+    pub remote_delete: Option<u64>,
+    /// Set when the message could not be decrypted because the sender's
+    /// identity key changed and is not trusted yet
+    #[serde(default)]
+    pub untrusted_identity: bool,
+    /// Disappearing messages timer of the conversation, `None` when off
+    #[serde(default)]
+    pub expires_in_seconds: Option<u32>,
+    /// Set when the message only changes the disappearing messages timer
+    #[serde(default)]
+    pub expiration_update: bool,
 }
 
-#[async_trait]
-pub trait SignalClient: Send + Sync + 'static {
-    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()>;
-    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>>;
+/// An emoji reaction to an earlier message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalReaction {
+    pub emoji: String,
+    /// Number or ACI uuid of whoever sent the message being reacted to
+    pub target_author: String,
+    /// Sent timestamp of the message being reacted to
+    pub target_timestamp: u64,
+    /// Whether the reaction is being taken back
+    pub remove: bool,
 }
 
-pub struct SignaldClient {
-    socket_path: String,
-    phone_number: String,
+/// A file attached to an incoming message
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SignalAttachment {
+    /// signal-cli's id for the attachment, also its file name in the store
+    pub id: Option<String>,
+    /// MIME type, e.g. `image/jpeg` or `audio/aac`
+    pub content_type: String,
+    /// Size in bytes, when known
+    pub size: Option<u64>,
+    /// Original file name chosen by the sender
+    pub filename: Option<String>,
+    /// Where the downloaded file lives on disk
+    pub path: Option<PathBuf>,
+}
+
+impl SignalAttachment {
+    /// Short human readable description, e.g. `report.pdf (application/pdf)`
+    pub fn describe(&self) -> String {
+        match &self.filename {
+            Some(name) => format!("{} ({})", name, self.content_type),
+            None => self.content_type.clone(),
+        }
+    }
+
+    /// Voice notes and other audio clips
+    pub fn is_audio(&self) -> bool {
+        self.content_type.starts_with("audio/")
+    }
+}
+
+/// Directory signal-cli downloads attachments into
+///
+/// signal-cli keeps its data under `$XDG_DATA_HOME/signal-cli`, falling back
+/// to `~/.local/share/signal-cli`.
+pub fn signal_cli_attachments_dir() -> PathBuf {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    data_home.join("signal-cli").join("attachments")
+}
+
+/// Parse signal-cli's `dataMessage.reaction` object
+fn parse_reaction(value: &serde_json::Value) -> Option<SignalReaction> {
+    Some(SignalReaction {
+        emoji: value.get("emoji")?.as_str()?.to_string(),
+        target_author: value
+            .get("targetAuthorNumber")
+            .and_then(|v| v.as_str())
+            .or_else(|| value.get("targetAuthor").and_then(|v| v.as_str()))
+            .unwrap_or("unknown")
+            .to_string(),
+        target_timestamp: value.get("targetSentTimestamp")?.as_u64()?,
+        remove: value
+            .get("isRemove")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+    })
+}
+
+/// Parse one entry of a signal-cli `dataMessage.attachments` array
+fn parse_attachment(value: &serde_json::Value) -> SignalAttachment {
+    let id = value.get("id").and_then(|v| v.as_str()).map(str::to_string);
+    SignalAttachment {
+        content_type: value
+            .get("contentType")
+            .and_then(|v| v.as_str())
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        size: value.get("size").and_then(|v| v.as_u64()),
+        filename: value
+            .get("filename")
+            .and_then(|v| v.as_str())
+            .map(str::to_string),
+        path: id.as_ref().map(|id| signal_cli_attachments_dir().join(id)),
+        id,
+    }
+}
+
+impl SignalMessage {
+    /// Whether any of `identities` (our number, uuid, ...) was @-mentioned
+    pub fn mentions_any(&self, identities: &[&str]) -> bool {
+        self.mentions
+            .iter()
+            .any(|m| identities.contains(&m.as_str()))
+    }
+
+    /// Every identifier the sender is known by: [`SignalMessage::from`]
+    /// plus their uuid and number
+    pub fn sender_ids(&self) -> Vec<&str> {
+        let mut ids = vec![self.from.as_str()];
+        for id in [&self.source.uuid, &self.source.number]
+            .into_iter()
+            .flatten()
+        {
+            if !ids.contains(&id.as_str()) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// Whether this is a new direct message someone wrote to the bot, rather
+    /// than group chatter, a reaction, an edit, a delete, a timer change or
+    /// a message that could not be read
+    pub fn is_new_direct_message(&self) -> bool {
+        self.group_id.is_none()
+            && self.reaction.is_none()
+            && self.remote_delete.is_none()
+            && self.edited_timestamp.is_none()
+            && !self.untrusted_identity
+            && !self.expiration_update
+    }
+
+    /// Where replies to this message go: the originating group, or the
+    /// sender directly
+    pub fn reply_recipient(&self) -> Recipient {
+        match &self.group_id {
+            Some(group_id) => Recipient::Group(group_id.clone()),
+            None => Recipient::Direct(self.from.clone()),
+        }
+    }
+
+    /// Build a reply that goes back where this message came from, quoting
+    /// it so the reply can't be mistaken for an answer to another question
+    ///
+    /// Edits are quoted by the original message's timestamp, which is how
+    /// Signal keeps identifying an edited message.
+    pub fn reply(&self, content: impl Into<String>) -> OutgoingMessage {
+        let reply = OutgoingMessage::new(self.reply_recipient(), content);
+        match self.edited_timestamp.or(self.timestamp) {
+            Some(timestamp) => reply.with_quote(Quote {
+                timestamp,
+                author: self.from.clone(),
+                text: self.content.clone(),
+            }),
+            None => reply,
+        }
+    }
+}
+
+/// Destination of an outgoing message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    /// A single user, by phone number or ACI uuid
+    Direct(String),
+    /// A group, by its base64 group id as reported by signal-cli
+    Group(String),
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Direct(to) => write!(f, "{to}"),
+            Self::Group(group_id) => write!(f, "group {group_id}"),
+        }
+    }
+}
+
+/// Whether a typing indicator is being shown or cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypingAction {
+    Started,
+    Stopped,
+}
+
+/// A message to be sent through a [`SignalClient`]
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    pub recipient: Recipient,
+    pub content: String,
+    /// Earlier message shown quoted above this one
+    pub quote: Option<Quote>,
+    /// Bold, italic, etc. applied to parts of `content`
+    pub styles: Vec<StyleRange>,
+}
+
+/// Reference to the message being replied to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    /// Sent timestamp of the quoted message
+    pub timestamp: u64,
+    /// Number or ACI uuid of the quoted message's author
+    pub author: String,
+    /// Text of the quoted message, shown in the quote preview
+    pub text: String,
 }
 
-impl SignaldClient {
-    pub fn new(socket_path: String, phone_number: String) -> Self {
+impl OutgoingMessage {
+    pub fn new(recipient: Recipient, content: impl Into<String>) -> Self {
         Self {
-            socket_path,
-            phone_number,
+            recipient,
+            content: content.into(),
+            quote: None,
+            styles: Vec::new(),
         }
     }
+
+    /// A direct message to a single user
+    pub fn direct(to: impl Into<String>, content: impl Into<String>) -> Self {
+        Self::new(Recipient::Direct(to.into()), content)
+    }
+
+    /// Quote an earlier message
+    pub fn with_quote(mut self, quote: Quote) -> Self {
+        self.quote = Some(quote);
+        self
+    }
+}
+
+/// How the bot's account presents itself in Signal
+#[derive(Debug, Clone, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct Profile {
+    /// Display name, e.g. "Senator Budd (Prep Bot)"
+    pub name: String,
+    /// Short "about" text shown under the name
+    pub about: Option<String>,
+    /// Emoji shown next to the about text
+    pub about_emoji: Option<String>,
+    /// JPEG or PNG image; `None` leaves the current avatar in place
+    pub avatar: Option<Vec<u8>>,
+}
+
+/// An avatar written out for signal-cli, which only takes a file path
+///
+/// The file is removed again when this is dropped.
+pub(crate) struct AvatarFile(PathBuf);
+
+impl AvatarFile {
+    pub(crate) async fn write(image: &[u8]) -> anyhow::Result<Self> {
+        let path = std::env::temp_dir().join(format!("signal-avatar-{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, image).await?;
+        Ok(Self(path))
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        self.0.to_str().unwrap_or_default()
+    }
 }
 
-#[derive(Serialize)]
-struct SignaldRequest {
-    #[serde(rename = "type")]
-    request_type: String,
-    account: String,
-    recipient: String,
-    message: String,
+impl Drop for AvatarFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
 }
 
-#[derive(Deserialize)]
-struct SignaldResponse {
-    // Note: These fields exist in the API response but aren't currently used
-    // Keeping the struct for future use when we need to parse signald responses
-    #[allow(dead_code)]
-    #[serde(rename = "type")]
-    response_type: String,
-    #[allow(dead_code)]
-    data: Option<serde_json::Value>,
+/// A send failed because the recipient's identity key changed
+///
+/// This happens when someone reinstalls Signal or switches phones. Sends to
+/// them fail until the new key is trusted with [`SignalClient::trust`].
+/// Transports return it inside their send error, so callers find it with
+/// `error.downcast_ref::<UntrustedIdentity>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UntrustedIdentity {
+    /// Number or ACI uuid whose identity key changed
+    pub number: String,
 }
 
-#[async_trait]
-impl SignalClient for SignaldClient {
-    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
-        let request = SignaldRequest {
-            request_type: "send".to_string(),
-            account: self.phone_number.clone(),
-            recipient: to.to_string(),
-            message: content.to_string(),
-        };
+impl fmt::Display for UntrustedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "untrusted identity for {}", self.number)
+    }
+}
 
-        let json_request = serde_json::to_string(&request)?;
-        info!("Sending Signal message via signald: {}", json_request);
+impl std::error::Error for UntrustedIdentity {}
 
-        // Use socat to send JSON to signald Unix socket
-        let mut cmd = Command::new("socat")
-            .arg("-")
-            .arg(format!("UNIX-CONNECT:{}", self.socket_path))
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
+/// Signal's servers refused a send because the account sent too much
+///
+/// Sending should pause until `retry_after` has passed. When Signal wants
+/// proof that a human runs the account it also hands out a `challenge`, which
+/// is lifted early by solving a captcha and passing the token to
+/// [`SignalClient::submit_rate_limit_challenge`]. Like
+/// [`UntrustedIdentity`], transports return it inside their send error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    /// Challenge token to solve with a captcha, when Signal offered one
+    pub challenge: Option<String>,
+    /// Seconds Signal asked us to wait, when it said
+    pub retry_after: Option<u64>,
+}
 
-        if let Some(stdin) = cmd.stdin.as_mut() {
-            use tokio::io::AsyncWriteExt;
-            stdin.write_all(json_request.as_bytes()).await?;
-            stdin.write_all(b"\n").await?;
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.challenge {
+            Some(_) => write!(f, "rate limited by Signal until a captcha is solved")?,
+            None => write!(f, "rate limited by Signal")?,
+        }
+        if let Some(seconds) = self.retry_after {
+            write!(f, " (retry after {seconds}s)")?;
         }
+        Ok(())
+    }
+}
 
-        let output = cmd.wait_with_output().await?;
-        if !output.status.success() {
-            anyhow::bail!(
-                "signald command failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
+impl std::error::Error for RateLimited {}
+
+/// The text in `failure` between `after` and the next `"`, e.g. the token in
+/// `challenge token "abc"`
+fn quoted_after<'a>(failure: &'a str, lower: &str, after: &str) -> Option<&'a str> {
+    let start = lower.find(after)? + after.len();
+    let rest = failure[start..].trim_start().strip_prefix('"')?;
+    rest.split('"').next().filter(|value| !value.is_empty())
+}
+
+/// Recognise signal-cli's rate limit and proof-required errors
+///
+/// signal-cli words challenges as `CAPTCHA proof required for sending to
+/// "+15550001111", available options "RECAPTCHA, PUSH_CHALLENGE" with
+/// challenge token "<token>", or wait "86400" seconds`.
+fn rate_limit_error(failure: &str) -> Option<RateLimited> {
+    let lower = failure.to_ascii_lowercase();
+    let challenged = lower.contains("proof required");
+    if !challenged && !lower.contains("rate limit") && !lower.contains("ratelimit") {
+        return None;
+    }
+    let retry_after = quoted_after(failure, &lower, "wait")
+        .or_else(|| {
+            let start = lower.find("retry after")? + "retry after".len();
+            failure[start..].split_whitespace().next()
+        })
+        .and_then(|seconds| {
+            seconds
+                .trim_matches(|c: char| !c.is_ascii_digit())
+                .parse()
+                .ok()
+        });
+    Some(RateLimited {
+        challenge: quoted_after(failure, &lower, "challenge token").map(str::to_string),
+        retry_after,
+    })
+}
+
+/// Build the error for a failed send, recognising untrusted identities and
+/// rate limits
+///
+/// signal-cli and signal-cli-rest-api report them only in their error text,
+/// e.g. `Untrusted Identity for "+15550001111"`. When the text names no
+/// number, a direct recipient is assumed to be the one.
+pub(crate) fn send_error(failure: String, recipient: &Recipient) -> anyhow::Error {
+    if let Some(limited) = rate_limit_error(&failure) {
+        return anyhow::Error::new(limited).context(failure);
+    }
+    let lower = failure.to_ascii_lowercase();
+    let Some(at) = lower.find("untrusted identit") else {
+        return anyhow::anyhow!(failure);
+    };
+    let named = failure[at..]
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| matches!(c, '"' | '\'' | ':' | ',' | '.')))
+        .find(|word| word.starts_with('+') || uuid::Uuid::parse_str(word).is_ok())
+        .map(str::to_string);
+    let number = match (named, recipient) {
+        (Some(number), _) => number,
+        (None, Recipient::Direct(number)) => number.clone(),
+        (None, Recipient::Group(_)) => return anyhow::anyhow!(failure),
+    };
+    anyhow::Error::new(UntrustedIdentity { number }).context(failure)
+}
+
+/// Whether signal-cli attached an untrusted identity exception to `value`
+///
+/// Envelopes that failed to decrypt carry the exception next to the
+/// envelope instead of a `dataMessage`.
+fn has_untrusted_identity(value: &serde_json::Value) -> bool {
+    ["exception", "error"]
+        .iter()
+        .filter_map(|key| value.get(key))
+        .flat_map(|exception| ["type", "message"].map(|field| exception.get(field)))
+        .filter_map(|text| text.and_then(|t| t.as_str()))
+        .any(|text| {
+            let text = text.to_ascii_lowercase().replace(' ', "");
+            text.contains("untrustedidentity")
+        })
+}
+
+/// What a signal-cli envelope carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeKind {
+    /// A message, edit, reaction or deletion sent to us
+    Data,
+    /// Our own account's activity mirrored from another of its devices, such
+    /// as a message the phone sent (`syncMessage.sentMessage`)
+    Sync,
+    /// Delivery or read receipt
+    Receipt,
+    /// Typing indicator
+    Typing,
+    /// Call offer, answer or hangup
+    Call,
+    /// Story post or reply
+    Story,
+    /// Nothing we recognise, e.g. an envelope that failed to decrypt
+    Other,
+}
+
+impl EnvelopeKind {
+    /// Classify an `envelope` object by the content it carries
+    pub fn of(envelope: &serde_json::Value) -> Self {
+        let has = |key: &str| envelope.get(key).is_some_and(|v| !v.is_null());
+        if has("syncMessage") {
+            Self::Sync
+        } else if has("dataMessage") || has("editMessage") {
+            Self::Data
+        } else if has("receiptMessage") {
+            Self::Receipt
+        } else if has("typingMessage") {
+            Self::Typing
+        } else if has("callMessage") {
+            Self::Call
+        } else if has("storyMessage") {
+            Self::Story
+        } else {
+            Self::Other
         }
+    }
+}
 
-        let response_text = String::from_utf8(output.stdout)?;
-        info!("signald response: {}", response_text);
+/// Parse who sent an envelope from `sourceUuid`, `sourceNumber` and
+/// `sourceName`
+///
+/// Older signal-cli versions only report `source`, which holds the number,
+/// or the uuid when the number is private.
+fn parse_source(envelope: &serde_json::Value) -> Option<SignalAddress> {
+    let field = |key: &str| {
+        envelope
+            .get(key)
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let mut address = SignalAddress {
+        uuid: field("sourceUuid"),
+        number: field("sourceNumber"),
+        username: None,
+        name: field("sourceName"),
+    };
+    if address.uuid.is_none() && address.number.is_none() {
+        let source = SignalAddress::parse(&field("source")?)?;
+        address.uuid = source.uuid;
+        address.number = source.number;
+    }
+    Some(address)
+}
+
+/// Parse a single signal-cli envelope into a [`SignalMessage`]
+///
+/// `value` is the object wrapping the envelope, as printed by
+/// `signal-cli receive --json` or sent as the params of a JSON-RPC
+/// `receive` notification. Edits (`editMessage`) are parsed like the
+/// message they carry. Envelopes that failed to decrypt because the sender's
+/// identity key changed come back empty, with
+/// [`SignalMessage::untrusted_identity`] set. Returns `None` for envelopes
+/// other than [`EnvelopeKind::Data`] (sync, receipts, typing, calls and
+/// stories), for messages our own account sent itself, and for data messages
+/// with no text, attachments, reaction or deletion.
+///
+/// `account` is usually the bot's number, so a message from our own account
+/// that only carries its uuid gets through; [`BotConfig::is_own_message`]
+/// catches those once `SIGNAL_ACCOUNT_UUID` is set.
+///
+/// [`BotConfig::is_own_message`]: crate::config::BotConfig::is_own_message
+pub fn parse_envelope(value: &serde_json::Value, account: &str) -> Option<SignalMessage> {
+    let envelope = value.get("envelope")?;
+    let source = parse_source(envelope)?;
+    let from = source.id();
+    if has_untrusted_identity(value) {
+        warn!(
+            "🔐 Could not read a message from {}: identity key changed",
+            from
+        );
+        return Some(SignalMessage {
+            from,
+            source,
+            to: account.to_string(),
+            timestamp: envelope.get("timestamp").and_then(|t| t.as_u64()),
+            untrusted_identity: true,
+            ..Default::default()
+        });
+    }
+    match EnvelopeKind::of(envelope) {
+        EnvelopeKind::Data => {}
+        kind => {
+            debug!("⏭️  Skipping {:?} envelope from {}", kind, from);
+            return None;
+        }
+    }
+    if source.is(account) {
+        debug!("⏭️  Skipping a message our own account sent to itself");
+        return None;
+    }
+    let edit = envelope.get("editMessage");
+    let edited_timestamp = edit
+        .and_then(|e| e.get("targetSentTimestamp"))
+        .and_then(|t| t.as_u64());
+    let data_message = match edit {
+        Some(edit) => edit.get("dataMessage")?,
+        None => envelope.get("dataMessage")?,
+    };
+    let remote_delete = data_message
+        .get("remoteDelete")
+        .and_then(|d| d.get("timestamp"))
+        .and_then(|t| t.as_u64());
+    let attachments: Vec<SignalAttachment> = data_message
+        .get("attachments")
+        .and_then(|a| a.as_array())
+        .map(|attachments| attachments.iter().map(parse_attachment).collect())
+        .unwrap_or_default();
+    let reaction = data_message.get("reaction").and_then(parse_reaction);
+    let message_text = data_message.get("message").and_then(|m| m.as_str());
+    let expiration_update = data_message
+        .get("isExpirationUpdate")
+        .and_then(|u| u.as_bool())
+        .unwrap_or(false);
+    if message_text.is_none()
+        && attachments.is_empty()
+        && reaction.is_none()
+        && remote_delete.is_none()
+        && !expiration_update
+    {
+        return None;
+    }
+
+    let content = message_text.unwrap_or("").to_string();
+    let timestamp = data_message
+        .get("timestamp")
+        .or_else(|| envelope.get("timestamp"))
+        .and_then(|t| t.as_u64());
+    let group_id = data_message
+        .get("groupInfo")
+        .and_then(|g| g.get("groupId"))
+        .and_then(|id| id.as_str())
+        .map(str::to_string);
+    let expires_in_seconds = data_message
+        .get("expiresInSeconds")
+        .and_then(|e| e.as_u64())
+        .filter(|&seconds| seconds > 0)
+        .map(|seconds| seconds as u32);
+    let mentions = data_message
+        .get("mentions")
+        .and_then(|m| m.as_array())
+        .map(|mentions| {
+            mentions
+                .iter()
+                .flat_map(|m| ["number", "uuid"].map(|key| m.get(key).and_then(|v| v.as_str())))
+                .flatten()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    match &group_id {
+        Some(group) => info!(
+            "📨 Received Signal message from {} in group {}: {}",
+            from, group, content
+        ),
+        None => info!("📨 Received Signal message from {}: {}", from, content),
+    }
+    if !attachments.is_empty() {
+        debug!("📎 Message carries {} attachment(s)", attachments.len());
+    }
+    Some(SignalMessage {
+        from,
+        source,
+        to: account.to_string(),
+        content,
+        group_id,
+        mentions,
+        attachments,
+        timestamp,
+        reaction,
+        edited_timestamp,
+        remote_delete,
+        untrusted_identity: false,
+        expires_in_seconds,
+        expiration_update,
+    })
+}
 
+/// Stream of incoming messages pushed by a transport as they arrive
+///
+/// The stream ending means the underlying connection went away; callers
+/// should subscribe again.
+pub type MessageStream = BoxStream<'static, SignalMessage>;
+
+#[async_trait]
+pub trait SignalClient: Send + Sync + 'static {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()>;
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>>;
+
+    /// Send a message to a user or a group
+    ///
+    /// Returns the timestamp Signal assigned to the sent message, when the
+    /// transport reports it. Reactions and quotes refer back to a message by
+    /// that timestamp.
+    ///
+    /// The default only handles direct messages via
+    /// [`SignalClient::send_message`]; transports that support groups
+    /// override it.
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        match &message.recipient {
+            Recipient::Direct(to) => {
+                self.send_message(to, &message.content).await?;
+                Ok(None)
+            }
+            Recipient::Group(_) => {
+                anyhow::bail!("group messages are not supported by this Signal transport")
+            }
+        }
+    }
+
+    /// Subscribe to incoming messages instead of polling for them
+    ///
+    /// Transports that can push (signal-cli JSON-RPC notifications, signald
+    /// subscriptions) return a stream. The default of `None` tells the worker
+    /// to fall back to polling [`SignalClient::receive_messages`].
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        Ok(None)
+    }
+
+    /// Show or clear a typing indicator for `to`
+    ///
+    /// Signal clients hide the indicator after about 15 seconds, so callers
+    /// refresh it while a reply is being prepared. The default does nothing.
+    async fn send_typing(&self, _to: &Recipient, _action: TypingAction) -> anyhow::Result<()> {
         Ok(())
     }
 
-    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
-        // For now, return empty - in a real implementation we'd listen to the signald socket
-        // or poll for new messages
-        warn!("receive_messages not fully implemented yet");
-        Ok(vec![])
+    /// Tell `to` that their message sent at `timestamp` has been read
+    ///
+    /// The default does nothing.
+    async fn send_read_receipt(&self, _to: &str, _timestamp: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Delete a message we sent to `to` at `timestamp` for everyone
+    async fn remote_delete(&self, _to: &Recipient, _timestamp: u64) -> anyhow::Result<()> {
+        anyhow::bail!("remote delete is not supported by this Signal transport")
+    }
+
+    /// Trust `number`'s current identity key after it changed
+    ///
+    /// Sends to `number` fail with [`UntrustedIdentity`] until this is done.
+    async fn trust(&self, _number: &str) -> anyhow::Result<()> {
+        anyhow::bail!("trusting identity keys is not supported by this Signal transport")
+    }
+
+    /// Set the disappearing messages timer for the conversation with `to`
+    ///
+    /// Messages we send there afterwards disappear after `seconds`; 0 turns
+    /// the timer off.
+    async fn set_expiration(&self, _to: &Recipient, _seconds: u32) -> anyhow::Result<()> {
+        anyhow::bail!("disappearing messages are not supported by this Signal transport")
+    }
+
+    /// Set the name, about text and avatar the bot's account shows
+    async fn update_profile(&self, _profile: &Profile) -> anyhow::Result<()> {
+        anyhow::bail!("updating the profile is not supported by this Signal transport")
+    }
+
+    /// Lift a [`RateLimited`] challenge with the token from a solved captcha
+    async fn submit_rate_limit_challenge(
+        &self,
+        _challenge: &str,
+        _captcha: &str,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("rate limit challenges are not supported by this Signal transport")
     }
 }
 
@@ -109,16 +835,44 @@ impl SignalCliClient {
     pub fn new(phone_number: String) -> Self {
         Self { phone_number }
     }
+
+    /// Run a signal-cli subcommand for our account, failing on a non-zero exit
+    async fn run(&self, args: &[&str]) -> anyhow::Result<()> {
+        let output = Command::new("signal-cli")
+            .arg("-a")
+            .arg(&self.phone_number)
+            .args(args)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!(
+                "❌ signal-cli {} failed with status: {}",
+                args[0], output.status
+            );
+            error!("📋 stderr: {}", stderr);
+            anyhow::bail!("signal-cli {} failed: {}", args[0], stderr);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl SignalClient for SignalCliClient {
     async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.send(&OutgoingMessage::direct(to, content))
+            .await
+            .map(|_| ())
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        let to = &message.recipient;
         info!("🔄 Attempting to send Signal message via signal-cli");
         debug!(
             "Signal CLI send - To: {}, Content length: {} chars",
             to,
-            content.len()
+            message.content.len()
         );
         debug!("Using phone number: {}", self.phone_number);
 
@@ -144,13 +898,26 @@ impl SignalClient for SignalCliClient {
             }
         }
 
-        let output = Command::new("signal-cli")
-            .arg("-a")
-            .arg(&self.phone_number)
-            .arg("send")
-            .arg(to)
-            .arg("-m")
-            .arg(content)
+        let mut command = Command::new("signal-cli");
+        command.arg("-a").arg(&self.phone_number).arg("send");
+        match to {
+            Recipient::Direct(number) => command.arg(number),
+            Recipient::Group(group_id) => command.arg("-g").arg(group_id),
+        };
+        command.arg("-m").arg(&message.content);
+        for style in &message.styles {
+            command.arg("--text-style").arg(style.to_string());
+        }
+        if let Some(quote) = &message.quote {
+            command
+                .arg("--quote-timestamp")
+                .arg(quote.timestamp.to_string())
+                .arg("--quote-author")
+                .arg(&quote.author)
+                .arg("--quote-message")
+                .arg(&quote.text);
+        }
+        let output = command
             .arg("--verbose") // Add verbose flag for better debugging
             .output()
             .await?;
@@ -165,7 +932,10 @@ impl SignalClient for SignalCliClient {
             );
             error!("📋 stderr: {}", stderr);
             error!("📋 stdout: {}", stdout);
-            anyhow::bail!("signal-cli send failed: {}", stderr);
+            return Err(send_error(
+                format!("signal-cli send failed: {}", stderr),
+                to,
+            ));
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -173,7 +943,96 @@ impl SignalClient for SignalCliClient {
         if !stdout.trim().is_empty() {
             debug!("📤 signal-cli output: {}", stdout);
         }
-        Ok(())
+        // signal-cli prints the sent message's timestamp on its own line
+        Ok(stdout
+            .lines()
+            .rev()
+            .find_map(|line| line.trim().parse().ok()))
+    }
+
+    async fn send_typing(&self, to: &Recipient, action: TypingAction) -> anyhow::Result<()> {
+        debug!("⌨️  Sending typing {:?} to {} via signal-cli", action, to);
+        let mut args = vec!["sendTyping"];
+        if action == TypingAction::Stopped {
+            args.push("--stop");
+        }
+        match to {
+            Recipient::Direct(number) => args.push(number),
+            Recipient::Group(group_id) => args.extend(["-g", group_id.as_str()]),
+        }
+        self.run(&args).await
+    }
+
+    async fn send_read_receipt(&self, to: &str, timestamp: u64) -> anyhow::Result<()> {
+        debug!("👀 Sending read receipt to {} for {}", to, timestamp);
+        let timestamp = timestamp.to_string();
+        self.run(&["sendReceipt", to, "-t", &timestamp, "--type", "read"])
+            .await
+    }
+
+    async fn remote_delete(&self, to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        info!("🗑️  Remote-deleting message {} sent to {}", timestamp, to);
+        let timestamp = timestamp.to_string();
+        let mut args = vec!["remoteDelete", "-t", &timestamp];
+        match to {
+            Recipient::Direct(number) => args.push(number),
+            Recipient::Group(group_id) => args.extend(["-g", group_id.as_str()]),
+        }
+        self.run(&args).await
+    }
+
+    async fn trust(&self, number: &str) -> anyhow::Result<()> {
+        info!("🔐 Trusting the new identity key of {}", number);
+        self.run(&["trust", "--trust-all-known-keys", number]).await
+    }
+
+    async fn set_expiration(&self, to: &Recipient, seconds: u32) -> anyhow::Result<()> {
+        info!(
+            "⏳ Setting the disappearing messages timer for {} to {}s",
+            to, seconds
+        );
+        let seconds = seconds.to_string();
+        let mut args = match to {
+            Recipient::Direct(number) => vec!["updateContact", number.as_str()],
+            Recipient::Group(group_id) => vec!["updateGroup", "-g", group_id.as_str()],
+        };
+        args.extend(["--expiration", seconds.as_str()]);
+        self.run(&args).await
+    }
+
+    async fn update_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        info!("🪪 Updating the Signal profile to \"{}\"", profile.name);
+        let mut args = vec!["updateProfile", "--name", profile.name.as_str()];
+        if let Some(about) = &profile.about {
+            args.extend(["--about", about.as_str()]);
+        }
+        if let Some(emoji) = &profile.about_emoji {
+            args.extend(["--about-emoji", emoji.as_str()]);
+        }
+        let avatar = match &profile.avatar {
+            Some(image) => Some(AvatarFile::write(image).await?),
+            None => None,
+        };
+        if let Some(avatar) = &avatar {
+            args.extend(["--avatar", avatar.path()]);
+        }
+        self.run(&args).await
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        challenge: &str,
+        captcha: &str,
+    ) -> anyhow::Result<()> {
+        info!("🧩 Submitting a rate limit challenge");
+        self.run(&[
+            "submitRateLimitChallenge",
+            "--challenge",
+            challenge,
+            "--captcha",
+            captcha,
+        ])
+        .await
     }
 
     async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
@@ -209,29 +1068,14 @@ impl SignalClient for SignalCliClient {
 
         debug!("📬 Raw signal-cli receive output: {}", response_text);
 
-        // Parse signal-cli JSON output (simplified - real parsing would be more robust)
-        let lines: Vec<&str> = response_text.lines().collect();
+        // Parse signal-cli JSON output, one envelope per line
         let mut messages = Vec::new();
 
-        for line in lines {
+        for line in response_text.lines() {
             debug!("🔍 Parsing line: {}", line);
             if let Ok(msg) = serde_json::from_str::<serde_json::Value>(line) {
-                if let (Some(envelope), Some(source)) = (
-                    msg.get("envelope"),
-                    msg.get("envelope").and_then(|e| e.get("source")),
-                ) {
-                    if let Some(data_message) = envelope.get("dataMessage") {
-                        if let Some(message_text) = data_message.get("message") {
-                            let from = source.as_str().unwrap_or("unknown").to_string();
-                            let content = message_text.as_str().unwrap_or("").to_string();
-                            info!("📨 Received Signal message from {}: {}", from, content);
-                            messages.push(SignalMessage {
-                                from,
-                                to: self.phone_number.clone(),
-                                content,
-                            });
-                        }
-                    }
+                if let Some(message) = parse_envelope(&msg, &self.phone_number) {
+                    messages.push(message);
                 }
             } else {
                 debug!("⚠️  Could not parse JSON line: {}", line);
@@ -0,0 +1,133 @@
+//! Plumbing shared by transports that keep a connection open
+//!
+//! A connection's reader task resolves responses through [`PendingRequests`]
+//! and hands every parsed incoming message to an [`Inbox`], which forwards it
+//! to live subscribers or buffers it until the next poll.
+
+use super::{MessageStream, SignalMessage};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// How long to wait for the other side to answer a single request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Receiving end for the response to one request
+pub(crate) type ResponseReceiver = oneshot::Receiver<anyhow::Result<Value>>;
+
+type ResponseSender = oneshot::Sender<anyhow::Result<Value>>;
+
+/// Requests that have been written and are waiting for a response
+pub(crate) struct PendingRequests {
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<String, ResponseSender>>,
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            waiting: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl PendingRequests {
+    /// Allocate a request id and a receiver for its response
+    pub(crate) fn register(&self) -> (String, ResponseReceiver) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst).to_string();
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(id.clone(), tx);
+        (id, rx)
+    }
+
+    /// Complete a request; returns false if nobody was waiting for `id`
+    pub(crate) fn resolve(&self, id: &str, result: anyhow::Result<Value>) -> bool {
+        match self.waiting.lock().unwrap().remove(id) {
+            Some(tx) => {
+                let _ = tx.send(result);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget a request whose write never made it out
+    pub(crate) fn cancel(&self, id: &str) {
+        self.waiting.lock().unwrap().remove(id);
+    }
+
+    /// Fail every outstanding request, e.g. when the connection drops
+    pub(crate) fn fail_all(&self, reason: &str) {
+        let waiting: Vec<_> = self.waiting.lock().unwrap().drain().collect();
+        for (_, tx) in waiting {
+            let _ = tx.send(Err(anyhow::anyhow!("{}", reason)));
+        }
+    }
+
+    /// Wait for the response to request `id`, giving up after a timeout
+    pub(crate) async fn wait(
+        &self,
+        id: &str,
+        rx: ResponseReceiver,
+        what: &str,
+    ) -> anyhow::Result<Value> {
+        match tokio::time::timeout(REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => anyhow::bail!("connection closed before answering {}", what),
+            Err(_) => {
+                self.cancel(id);
+                anyhow::bail!("timed out waiting for {} response", what)
+            }
+        }
+    }
+}
+
+/// Incoming messages, pushed to subscribers or buffered until polled
+#[derive(Default)]
+pub(crate) struct Inbox {
+    buffered: Mutex<Vec<SignalMessage>>,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<SignalMessage>>>,
+}
+
+impl Inbox {
+    /// Hand a message to live subscribers, or buffer it for polling
+    pub(crate) fn deliver(&self, message: SignalMessage) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| !tx.is_closed());
+        if subscribers.is_empty() {
+            self.buffered.lock().unwrap().push(message);
+            return;
+        }
+        for tx in subscribers.iter() {
+            let _ = tx.unbounded_send(message.clone());
+        }
+    }
+
+    /// Take everything buffered since the last poll
+    pub(crate) fn drain(&self) -> Vec<SignalMessage> {
+        self.buffered.lock().unwrap().drain(..).collect()
+    }
+
+    /// Open a new stream, starting with anything already buffered
+    pub(crate) fn subscribe(&self) -> MessageStream {
+        let (tx, rx) = mpsc::unbounded();
+        // The subscriber lock is held so nothing slips into the buffer meanwhile
+        let mut subscribers = self.subscribers.lock().unwrap();
+        for message in self.buffered.lock().unwrap().drain(..) {
+            let _ = tx.unbounded_send(message);
+        }
+        subscribers.push(tx);
+        rx.boxed()
+    }
+
+    /// End every open stream, prompting subscribers to resubscribe
+    pub(crate) fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
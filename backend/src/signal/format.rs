@@ -0,0 +1,416 @@
+//! Outbound formatting: markdown to Signal text styles, long bodies to parts
+//!
+//! LLM answers are written in markdown, which Signal shows as raw asterisks.
+//! [`render_markdown`] strips the markup and records where each style applies,
+//! as the body ranges Signal uses for bold, italic and the rest. Ranges count
+//! UTF-16 code units, like Signal clients do. [`prepare`] then splits bodies
+//! that are too long to read comfortably into parts, breaking at paragraphs
+//! where it can.
+
+use super::{OutgoingMessage, SignalClient};
+use std::fmt;
+
+/// A Signal text style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextStyle {
+    Bold,
+    Italic,
+    Strikethrough,
+    Monospace,
+    Spoiler,
+}
+
+impl TextStyle {
+    /// Name used by signal-cli's `--text-style`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bold => "BOLD",
+            Self::Italic => "ITALIC",
+            Self::Strikethrough => "STRIKETHROUGH",
+            Self::Monospace => "MONOSPACE",
+            Self::Spoiler => "SPOILER",
+        }
+    }
+}
+
+/// A style applied to part of a message body
+///
+/// `start` and `length` count UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StyleRange {
+    pub start: usize,
+    pub length: usize,
+    pub style: TextStyle,
+}
+
+/// `start:length:STYLE`, the format signal-cli takes
+impl fmt::Display for StyleRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.start, self.length, self.style.as_str())
+    }
+}
+
+/// Plain text with the styles that apply to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormattedText {
+    pub text: String,
+    pub styles: Vec<StyleRange>,
+}
+
+/// Inline markers, longest first so `**` wins over `*`
+const INLINE_MARKERS: &[(&str, TextStyle)] = &[
+    ("**", TextStyle::Bold),
+    ("__", TextStyle::Bold),
+    ("~~", TextStyle::Strikethrough),
+    ("||", TextStyle::Spoiler),
+    ("*", TextStyle::Italic),
+    ("_", TextStyle::Italic),
+];
+
+/// Accumulates plain text while tracking its length in UTF-16 code units
+#[derive(Default)]
+struct Builder {
+    text: String,
+    utf16_len: usize,
+    styles: Vec<StyleRange>,
+}
+
+impl Builder {
+    fn push_str(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.utf16_len += s.encode_utf16().count();
+    }
+
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.utf16_len += c.len_utf16();
+    }
+
+    /// Style everything pushed since `start`
+    fn style_from(&mut self, start: usize, style: TextStyle) {
+        if self.utf16_len > start {
+            self.styles.push(StyleRange {
+                start,
+                length: self.utf16_len - start,
+                style,
+            });
+        }
+    }
+}
+
+/// Convert markdown into plain text and Signal style ranges
+///
+/// Handles the markdown LLMs tend to produce: bold, italic, strikethrough,
+/// inline code and fenced code blocks, headings (shown bold), bullet lists
+/// (shown with `•`), links (shown as `text (url)`) and horizontal rules.
+/// Anything else is left as written.
+pub fn render_markdown(markdown: &str) -> FormattedText {
+    let mut out = Builder::default();
+    let mut in_code_block = false;
+    let mut first_line = true;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+
+        if !first_line {
+            out.push('\n');
+        }
+        first_line = false;
+
+        if in_code_block {
+            let start = out.utf16_len;
+            out.push_str(line);
+            out.style_from(start, TextStyle::Monospace);
+        } else if is_horizontal_rule(trimmed) {
+            // Rendered as the blank line it visually stands for
+        } else if let Some(heading) = heading_text(trimmed) {
+            let start = out.utf16_len;
+            render_inline(heading, &mut out);
+            out.style_from(start, TextStyle::Bold);
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet))
+        {
+            out.push_str(&line[..line.len() - trimmed.len()]);
+            out.push_str("• ");
+            render_inline(item, &mut out);
+        } else {
+            render_inline(line, &mut out);
+        }
+    }
+
+    let text = out.text.trim_end();
+    out.text.truncate(text.len());
+    let utf16_len = out.text.encode_utf16().count();
+    out.styles.retain_mut(|range| {
+        range.length = range.length.min(utf16_len.saturating_sub(range.start));
+        range.length > 0
+    });
+    FormattedText {
+        text: out.text,
+        styles: out.styles,
+    }
+}
+
+fn is_horizontal_rule(line: &str) -> bool {
+    ['-', '*', '_'].iter().any(|&marker| {
+        line.chars().all(|c| c == marker || c == ' ')
+            && line.chars().filter(|&c| c == marker).count() >= 3
+    })
+}
+
+/// Text of an ATX heading (`# Title`), if `line` is one
+fn heading_text(line: &str) -> Option<&str> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    line[level..]
+        .strip_prefix(' ')
+        .map(|text| text.trim().trim_end_matches('#').trim_end())
+}
+
+/// Render the inline markup of a single line into `out`
+fn render_inline(line: &str, out: &mut Builder) {
+    let mut rest = line;
+    let mut prev: Option<char> = None;
+
+    'outer: while let Some(c) = rest.chars().next() {
+        // Backslash escapes keep the next punctuation character literal
+        if c == '\\' {
+            if let Some(escaped) = rest[1..].chars().next().filter(char::is_ascii_punctuation) {
+                out.push(escaped);
+                prev = Some(escaped);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        }
+
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`').filter(|&end| end > 0) {
+                let start = out.utf16_len;
+                out.push_str(&rest[1..1 + end]);
+                out.style_from(start, TextStyle::Monospace);
+                prev = Some('`');
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+
+        if c == '[' {
+            if let Some(link) = parse_link(rest) {
+                render_inline(link.text, out);
+                out.push_str(" (");
+                out.push_str(link.url);
+                out.push(')');
+                prev = Some(')');
+                rest = &rest[link.len..];
+                continue;
+            }
+        }
+
+        for &(marker, style) in INLINE_MARKERS {
+            if !rest.starts_with(marker) {
+                continue;
+            }
+            // `_` only counts at word boundaries, so snake_case stays intact
+            if marker.starts_with('_') && prev.is_some_and(char::is_alphanumeric) {
+                continue;
+            }
+            if let Some(end) = closing_marker(rest, marker) {
+                let inner = &rest[marker.len()..end];
+                let after = rest[end + marker.len()..].chars().next();
+                let padded =
+                    inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace);
+                if inner.is_empty()
+                    || padded
+                    || (marker.starts_with('_') && after.is_some_and(char::is_alphanumeric))
+                {
+                    continue;
+                }
+                let start = out.utf16_len;
+                render_inline(inner, out);
+                out.style_from(start, style);
+                prev = marker.chars().last();
+                rest = &rest[end + marker.len()..];
+                continue 'outer;
+            }
+        }
+
+        out.push(c);
+        prev = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+/// Byte offset of the marker closing the one `rest` starts with
+///
+/// A single `*` or `_` skips over doubled ones, so `*a **b** c*` closes at the
+/// last star.
+fn closing_marker(rest: &str, marker: &str) -> Option<usize> {
+    let doubled = marker.repeat(2);
+    let mut i = marker.len();
+    while i < rest.len() {
+        let tail = &rest[i..];
+        if marker.len() == 1 && tail.starts_with(&doubled) {
+            i += 2;
+        } else if tail.starts_with(marker) {
+            return Some(i);
+        } else {
+            i += tail.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// A markdown link, `[text](url)`
+struct Link<'a> {
+    text: &'a str,
+    url: &'a str,
+    /// Byte length of the whole link in the markdown
+    len: usize,
+}
+
+/// The link at the start of `rest`, if there is one
+fn parse_link(rest: &str) -> Option<Link<'_>> {
+    let text_end = rest.find("](")?;
+    let url_end = text_end + 2 + rest[text_end + 2..].find(')')?;
+    let text = &rest[1..text_end];
+    let url = &rest[text_end + 2..url_end];
+    if text.is_empty() || url.is_empty() || url.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(Link {
+        text,
+        url,
+        len: url_end + 1,
+    })
+}
+
+/// Split `formatted` into parts of at most `max_chars` characters
+///
+/// Parts break at the last paragraph break that fits, falling back to a line
+/// break, then a space, then a hard cut. Styles are clipped to each part and
+/// re-based on its start.
+pub fn split(formatted: &FormattedText, max_chars: usize) -> Vec<FormattedText> {
+    let text = formatted.text.as_str();
+    let max_chars = max_chars.max(1);
+    let mut parts = Vec::new();
+    let mut pos = 0;
+
+    while pos < text.len() {
+        let remaining = &text[pos..];
+        let (end, next) = match remaining.char_indices().nth(max_chars) {
+            None => (text.len(), text.len()),
+            Some((limit, _)) => {
+                let window = &remaining[..limit];
+                let cut = ["\n\n", "\n", " "].iter().find_map(|sep| {
+                    window
+                        .rfind(sep)
+                        .filter(|&at| at > 0)
+                        .map(|at| (pos + at, pos + at + sep.len()))
+                });
+                cut.unwrap_or((pos + limit, pos + limit))
+            }
+        };
+
+        let part = text[pos..end].trim();
+        if !part.is_empty() {
+            let part_start = pos + (text[pos..end].len() - text[pos..end].trim_start().len());
+            parts.push(slice(formatted, part_start, part_start + part.len()));
+        }
+        pos = next;
+    }
+    parts
+}
+
+/// The part of `formatted` between byte offsets `start` and `end`
+fn slice(formatted: &FormattedText, start: usize, end: usize) -> FormattedText {
+    let text = &formatted.text;
+    let from = text[..start].encode_utf16().count();
+    let to = from + text[start..end].encode_utf16().count();
+    let styles = formatted
+        .styles
+        .iter()
+        .filter_map(|range| {
+            let s = range.start.max(from);
+            let e = (range.start + range.length).min(to);
+            (s < e).then(|| StyleRange {
+                start: s - from,
+                length: e - s,
+                style: range.style,
+            })
+        })
+        .collect();
+    FormattedText {
+        text: text[start..end].to_string(),
+        styles,
+    }
+}
+
+/// Turn an outgoing markdown message into the Signal messages that carry it
+///
+/// Only the first part quotes the message being replied to; the rest follow
+/// it in order.
+pub fn prepare(message: &OutgoingMessage, max_chars: usize) -> Vec<OutgoingMessage> {
+    let formatted = render_markdown(&message.content);
+    let mut parts = split(&formatted, max_chars);
+    if parts.is_empty() && !message.content.trim().is_empty() {
+        // Nothing but markup (e.g. a lone rule), so send it as written
+        parts = split(
+            &FormattedText {
+                text: message.content.trim().to_string(),
+                styles: Vec::new(),
+            },
+            max_chars,
+        );
+    }
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| OutgoingMessage {
+            recipient: message.recipient.clone(),
+            content: part.text,
+            quote: if i == 0 { message.quote.clone() } else { None },
+            styles: part.styles,
+        })
+        .collect()
+}
+
+/// Parts of a message already sent, so an interrupted send can carry on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartsSent {
+    /// Sent timestamp of each part that went out, in part order; `None`
+    /// where the transport did not report one
+    pub timestamps: Vec<Option<u64>>,
+}
+
+impl PartsSent {
+    /// How many parts went out
+    pub fn count(&self) -> usize {
+        self.timestamps.len()
+    }
+}
+
+/// Format `message` and send its parts in order, skipping those already in
+/// `sent`
+///
+/// Stops at the first part that fails to send. `sent` is updated as each
+/// part goes out, so calling again with it resumes from the failed part
+/// instead of sending the earlier ones twice.
+pub async fn send_formatted(
+    client: &dyn SignalClient,
+    message: &OutgoingMessage,
+    max_chars: usize,
+    sent: &mut PartsSent,
+) -> anyhow::Result<()> {
+    for part in prepare(message, max_chars).into_iter().skip(sent.count()) {
+        let timestamp = client.send(&part).await?;
+        sent.timestamps.push(timestamp);
+    }
+    Ok(())
+}
@@ -0,0 +1,404 @@
+//! Persistent signal-cli JSON-RPC client
+//!
+//! Launches `signal-cli jsonRpc` once and exchanges newline-delimited
+//! JSON-RPC 2.0 messages over the child's stdin/stdout, so sends and
+//! receives no longer pay a JVM start-up each. Incoming messages arrive as
+//! `receive` notifications and are buffered until the next poll. If the
+//! child process dies it is restarted on the next call.
+
+use super::dispatch::{Inbox, PendingRequests};
+use super::{
+    parse_envelope, send_error, AvatarFile, MessageStream, OutgoingMessage, Profile, Recipient,
+    SignalClient, SignalMessage, TypingAction,
+};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+/// Signal client backed by a long-running `signal-cli jsonRpc` process
+pub struct SignalJsonRpcClient {
+    phone_number: String,
+    program: String,
+    args: Vec<String>,
+    connection: Mutex<Option<Connection>>,
+    inbox: Arc<Inbox>,
+}
+
+/// A running signal-cli child process
+///
+/// Requests wait on the process they were written to, so one that exits
+/// only fails its own requests, not those sent to its replacement.
+struct Connection {
+    child: Child,
+    stdin: ChildStdin,
+    pending: Arc<PendingRequests>,
+    alive: Arc<AtomicBool>,
+}
+
+impl Connection {
+    fn is_alive(&mut self) -> bool {
+        self.alive.load(Ordering::SeqCst) && matches!(self.child.try_wait(), Ok(None))
+    }
+}
+
+impl SignalJsonRpcClient {
+    /// Create a client that runs `signal-cli -a <phone_number> jsonRpc`
+    pub fn new(phone_number: String) -> Self {
+        let args = vec![
+            "-a".to_string(),
+            phone_number.clone(),
+            "jsonRpc".to_string(),
+        ];
+        Self::with_command(phone_number, "signal-cli", args)
+    }
+
+    /// Create a client that runs an arbitrary JSON-RPC speaking command
+    ///
+    /// Mainly useful for tests, which substitute a fake signal-cli script.
+    pub fn with_command(
+        phone_number: String,
+        program: impl Into<String>,
+        args: Vec<String>,
+    ) -> Self {
+        Self {
+            phone_number,
+            program: program.into(),
+            args,
+            connection: Mutex::new(None),
+            inbox: Arc::new(Inbox::default()),
+        }
+    }
+
+    /// Send a JSON-RPC request and wait for the matching response
+    async fn request(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let mut guard = self.connection.lock().await;
+        let connection = self.ensure_connected(&mut guard)?;
+        let pending = connection.pending.clone();
+        let (id, rx) = pending.register();
+
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": id,
+        });
+        debug!("📤 signal-cli jsonRpc request: {}", payload);
+
+        if let Err(e) = write_line(connection, &payload.to_string()).await {
+            error!("❌ Failed to write to signal-cli jsonRpc: {}", e);
+            pending.cancel(&id);
+            *guard = None;
+            anyhow::bail!("signal-cli jsonRpc write failed: {}", e);
+        }
+        drop(guard);
+
+        pending
+            .wait(&id, rx, &format!("signal-cli jsonRpc {method}"))
+            .await
+    }
+
+    /// Start the child process if it is not already running
+    async fn connect(&self) -> anyhow::Result<()> {
+        let mut guard = self.connection.lock().await;
+        self.ensure_connected(&mut guard)?;
+        Ok(())
+    }
+
+    /// Make sure a live child process exists, spawning one if necessary
+    fn ensure_connected<'a>(
+        &self,
+        slot: &'a mut Option<Connection>,
+    ) -> anyhow::Result<&'a mut Connection> {
+        if let Some(connection) = slot.as_mut() {
+            if !connection.is_alive() {
+                warn!("⚠️  signal-cli jsonRpc process died, restarting");
+                *slot = None;
+            }
+        }
+
+        if slot.is_none() {
+            *slot = Some(self.spawn()?);
+        }
+
+        Ok(slot.as_mut().expect("connection was just established"))
+    }
+
+    fn spawn(&self) -> anyhow::Result<Connection> {
+        info!("🚀 Starting {} {}", self.program, self.args.join(" "));
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("failed to start {}: {}", self.program, e))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("signal-cli jsonRpc stdin unavailable"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("signal-cli jsonRpc stdout unavailable"))?;
+
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    debug!("📋 signal-cli: {}", line);
+                }
+            });
+        }
+
+        let pending = Arc::new(PendingRequests::default());
+        let alive = Arc::new(AtomicBool::new(true));
+        tokio::spawn(read_loop(
+            stdout,
+            pending.clone(),
+            self.inbox.clone(),
+            alive.clone(),
+            self.phone_number.clone(),
+        ));
+
+        Ok(Connection {
+            child,
+            stdin,
+            pending,
+            alive,
+        })
+    }
+}
+
+/// Write one line to the child's stdin
+async fn write_line(connection: &mut Connection, line: &str) -> std::io::Result<()> {
+    connection.stdin.write_all(line.as_bytes()).await?;
+    connection.stdin.write_all(b"\n").await?;
+    connection.stdin.flush().await
+}
+
+/// Dispatch everything the child writes to stdout until it exits
+async fn read_loop(
+    stdout: ChildStdout,
+    pending: Arc<PendingRequests>,
+    inbox: Arc<Inbox>,
+    alive: Arc<AtomicBool>,
+    account: String,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&line) {
+            Ok(value) => handle_incoming(&pending, &inbox, &account, value),
+            Err(_) => debug!("⚠️  Could not parse signal-cli jsonRpc line: {}", line),
+        }
+    }
+
+    warn!("⚠️  signal-cli jsonRpc stdout closed");
+    alive.store(false, Ordering::SeqCst);
+    pending.fail_all("signal-cli jsonRpc process exited");
+    // Ending the streams prompts subscribers to resubscribe, which restarts
+    // the process.
+    inbox.close();
+}
+
+/// Route a response to its waiting request, or buffer a received message
+fn handle_incoming(pending: &PendingRequests, inbox: &Inbox, account: &str, value: Value) {
+    if let Some(id) = value.get("id").filter(|_| value.get("method").is_none()) {
+        let id = match id {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let result = match value.get("error") {
+            Some(error) => {
+                let message = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown error");
+                Err(anyhow::anyhow!("signal-cli jsonRpc error: {}", message))
+            }
+            None => Ok(value.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        if !pending.resolve(&id, result) {
+            debug!("⚠️  signal-cli jsonRpc response for unknown id {}", id);
+        }
+        return;
+    }
+
+    match value.get("method").and_then(Value::as_str) {
+        Some("receive") => {
+            if let Some(message) = value.get("params").and_then(|p| parse_envelope(p, account)) {
+                inbox.deliver(message);
+            }
+        }
+        Some(method) => debug!("🔔 Ignoring signal-cli jsonRpc notification: {}", method),
+        None => debug!("⚠️  Unrecognised signal-cli jsonRpc message: {}", value),
+    }
+}
+
+#[async_trait]
+impl SignalClient for SignalJsonRpcClient {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.send(&OutgoingMessage::direct(to, content))
+            .await
+            .map(|_| ())
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        let to = &message.recipient;
+        info!("🔄 Sending Signal message via signal-cli jsonRpc");
+        debug!(
+            "Signal jsonRpc send - To: {}, Content length: {} chars",
+            to,
+            message.content.len()
+        );
+
+        let mut params = json!({ "message": message.content });
+        match to {
+            Recipient::Direct(number) => params["recipient"] = json!([number]),
+            Recipient::Group(group_id) => params["groupId"] = json!(group_id),
+        }
+        if !message.styles.is_empty() {
+            let styles: Vec<String> = message.styles.iter().map(ToString::to_string).collect();
+            params["textStyle"] = json!(styles);
+        }
+        if let Some(quote) = &message.quote {
+            params["quoteTimestamp"] = json!(quote.timestamp);
+            params["quoteAuthor"] = json!(quote.author);
+            params["quoteMessage"] = json!(quote.text);
+        }
+        let result = self
+            .request("send", params)
+            .await
+            .map_err(|e| send_error(e.to_string(), to))?;
+
+        info!("✅ Signal message sent successfully to {}", to);
+        debug!("📤 signal-cli jsonRpc send result: {}", result);
+        Ok(result.get("timestamp").and_then(Value::as_u64))
+    }
+
+    async fn send_typing(&self, to: &Recipient, action: TypingAction) -> anyhow::Result<()> {
+        debug!("⌨️  Sending typing {:?} to {} via jsonRpc", action, to);
+        let mut params = json!({ "stop": action == TypingAction::Stopped });
+        match to {
+            Recipient::Direct(number) => params["recipient"] = json!([number]),
+            Recipient::Group(group_id) => params["groupId"] = json!(group_id),
+        }
+        self.request("sendTyping", params).await?;
+        Ok(())
+    }
+
+    async fn send_read_receipt(&self, to: &str, timestamp: u64) -> anyhow::Result<()> {
+        debug!("👀 Sending read receipt to {} for {}", to, timestamp);
+        let params = json!({
+            "recipient": to,
+            "targetTimestamp": [timestamp],
+            "type": "read",
+        });
+        self.request("sendReceipt", params).await?;
+        Ok(())
+    }
+
+    async fn remote_delete(&self, to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        info!("🗑️  Remote-deleting message {} sent to {}", timestamp, to);
+        let mut params = json!({ "targetTimestamp": timestamp });
+        match to {
+            Recipient::Direct(number) => params["recipient"] = json!([number]),
+            Recipient::Group(group_id) => params["groupId"] = json!(group_id),
+        }
+        self.request("remoteDelete", params).await?;
+        Ok(())
+    }
+
+    async fn trust(&self, number: &str) -> anyhow::Result<()> {
+        info!("🔐 Trusting the new identity key of {}", number);
+        let params = json!({ "recipient": [number], "trustAllKnownKeys": true });
+        self.request("trust", params).await?;
+        Ok(())
+    }
+
+    async fn set_expiration(&self, to: &Recipient, seconds: u32) -> anyhow::Result<()> {
+        info!(
+            "⏳ Setting the disappearing messages timer for {} to {}s",
+            to, seconds
+        );
+        let (method, params) = match to {
+            Recipient::Direct(number) => (
+                "updateContact",
+                json!({ "recipient": number, "expiration": seconds }),
+            ),
+            Recipient::Group(group_id) => (
+                "updateGroup",
+                json!({ "groupId": group_id, "expiration": seconds }),
+            ),
+        };
+        self.request(method, params).await?;
+        Ok(())
+    }
+
+    async fn update_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        info!("🪪 Updating the Signal profile to \"{}\"", profile.name);
+        let mut params = json!({ "name": profile.name });
+        if let Some(about) = &profile.about {
+            params["about"] = json!(about);
+        }
+        if let Some(emoji) = &profile.about_emoji {
+            params["aboutEmoji"] = json!(emoji);
+        }
+        // The daemon is our own child process, so it can read our temp files
+        let avatar = match &profile.avatar {
+            Some(image) => Some(AvatarFile::write(image).await?),
+            None => None,
+        };
+        if let Some(avatar) = &avatar {
+            params["avatar"] = json!(avatar.path());
+        }
+        self.request("updateProfile", params).await?;
+        Ok(())
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        challenge: &str,
+        captcha: &str,
+    ) -> anyhow::Result<()> {
+        info!("🧩 Submitting a rate limit challenge via jsonRpc");
+        self.request(
+            "submitRateLimitChallenge",
+            json!({ "challenge": challenge, "captcha": captcha }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
+        // Polling doubles as a liveness check so a crashed daemon gets restarted
+        // even when nothing is being sent.
+        self.connect().await?;
+
+        let messages = self.inbox.drain();
+        if messages.is_empty() {
+            debug!("📭 No new Signal messages");
+        } else {
+            info!("📬 Received {} Signal messages via jsonRpc", messages.len());
+        }
+        Ok(messages)
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        self.connect().await?;
+
+        let stream = self.inbox.subscribe();
+        info!("📡 Subscribed to signal-cli jsonRpc receive notifications");
+        Ok(Some(stream))
+    }
+}
@@ -0,0 +1,542 @@
+//! signal-cli-rest-api client
+//!
+//! Talks to the `bbernhard/signal-cli-rest-api` container over HTTP instead
+//! of running signal-cli in this image. Messages are sent with
+//! `POST /v2/send`. Incoming messages are polled from `GET /v1/receive`,
+//! or pushed over the same path as a websocket when the container runs in
+//! `json-rpc` mode.
+
+use super::{
+    parse_envelope, send_error, MessageStream, OutgoingMessage, Profile, Recipient, SignalClient,
+    SignalMessage, StyleRange, TextStyle, TypingAction,
+};
+use async_trait::async_trait;
+use base64::Engine;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, error, info, warn};
+
+pub struct SignalRestClient {
+    base_url: String,
+    phone_number: String,
+    websocket: bool,
+    http: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct RestSendRequest<'a> {
+    number: &'a str,
+    recipients: Vec<String>,
+    message: &'a str,
+    /// `styled` when `message` carries the REST API's style markup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_author: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quote_message: Option<&'a str>,
+}
+
+/// The sent timestamp comes back as a string, e.g. `{"timestamp": "1700000000000"}`
+#[derive(Deserialize, Default)]
+struct RestSendResponse {
+    timestamp: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RestTypingRequest {
+    recipient: String,
+}
+
+#[derive(Serialize)]
+struct RestReceiptRequest<'a> {
+    receipt_type: &'static str,
+    recipient: &'a str,
+    timestamp: u64,
+}
+
+#[derive(Serialize)]
+struct RestRemoteDeleteRequest {
+    recipient: String,
+    timestamp: u64,
+}
+
+#[derive(Serialize)]
+struct RestTrustRequest {
+    trust_all_known_keys: bool,
+}
+
+#[derive(Serialize)]
+struct RestContactExpirationRequest<'a> {
+    recipient: &'a str,
+    expiration_in_seconds: u32,
+}
+
+#[derive(Serialize)]
+struct RestGroupExpirationRequest {
+    expiration_time: u32,
+}
+
+#[derive(Serialize)]
+struct RestProfileRequest<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    about: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base64_avatar: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RestChallengeRequest<'a> {
+    challenge_token: &'a str,
+    captcha: &'a str,
+}
+
+#[derive(Deserialize)]
+struct RestErrorResponse {
+    error: String,
+    /// Handed out with send errors when Signal wants a captcha solved
+    #[serde(default)]
+    challenge_tokens: Vec<String>,
+}
+
+impl SignalRestClient {
+    /// Create a client for the REST API at `base_url`, e.g. `http://signal-api:8080`
+    pub fn new(base_url: String, phone_number: String) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            phone_number,
+            websocket: false,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Receive over the websocket endpoint (REST API in `json-rpc` mode)
+    pub fn with_websocket(mut self) -> Self {
+        self.websocket = true;
+        self
+    }
+
+    fn receive_path(&self) -> String {
+        format!("/v1/receive/{}", self.phone_number)
+    }
+
+    fn websocket_url(&self) -> String {
+        let base = if let Some(rest) = self.base_url.strip_prefix("https://") {
+            format!("wss://{rest}")
+        } else if let Some(rest) = self.base_url.strip_prefix("http://") {
+            format!("ws://{rest}")
+        } else {
+            self.base_url.clone()
+        };
+        format!("{}{}", base, self.receive_path())
+    }
+}
+
+/// The REST API addresses groups as `group.` + base64 of signal-cli's group id
+fn rest_recipient(recipient: &Recipient) -> String {
+    match recipient {
+        Recipient::Direct(number) => number.clone(),
+        Recipient::Group(group_id) => format!(
+            "group.{}",
+            base64::engine::general_purpose::STANDARD.encode(group_id)
+        ),
+    }
+}
+
+/// Where one style marker goes in the styled text
+struct Marker {
+    /// UTF-16 offset in the plain text
+    offset: usize,
+    closes: bool,
+    /// Length of the styled span, so nested spans open and close in order
+    span: usize,
+    text: &'static str,
+}
+
+/// Write `styles` back into `text` as the REST API's `styled` markup
+///
+/// The REST API takes no style ranges; in `styled` text mode it parses
+/// `**bold**`, `*italic*`, `~strikethrough~`, `||spoiler||` and
+/// `` `monospace` `` out of the message instead.
+fn styled_text(text: &str, styles: &[StyleRange]) -> String {
+    let mut markers = Vec::new();
+    for range in styles {
+        let marker = match range.style {
+            TextStyle::Bold => "**",
+            TextStyle::Italic => "*",
+            TextStyle::Strikethrough => "~",
+            TextStyle::Monospace => "`",
+            TextStyle::Spoiler => "||",
+        };
+        for (offset, closes) in [(range.start, false), (range.start + range.length, true)] {
+            markers.push(Marker {
+                offset,
+                closes,
+                span: range.length,
+                text: marker,
+            });
+        }
+    }
+    // At a shared offset spans close before others open, inner spans close
+    // first and outer spans open first
+    markers.sort_by(|a, b| {
+        a.offset
+            .cmp(&b.offset)
+            .then(b.closes.cmp(&a.closes))
+            .then(if a.closes {
+                a.span.cmp(&b.span)
+            } else {
+                b.span.cmp(&a.span)
+            })
+    });
+
+    let mut styled = String::with_capacity(text.len() + markers.len() * 2);
+    let mut markers = markers.into_iter().peekable();
+    let mut offset = 0;
+    for c in text.chars() {
+        while let Some(marker) = markers.next_if(|m| m.offset <= offset) {
+            styled.push_str(marker.text);
+        }
+        styled.push(c);
+        offset += c.len_utf16();
+    }
+    for marker in markers {
+        styled.push_str(marker.text);
+    }
+    styled
+}
+
+/// Pull the REST API's `{"error": "..."}` message out of a failed response
+pub(crate) async fn error_message(resp: reqwest::Response) -> String {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    match serde_json::from_str::<RestErrorResponse>(&body) {
+        // Worded like signal-cli's own challenge errors, so send_error finds it
+        Ok(err) => match err.challenge_tokens.first() {
+            Some(token) => format!(
+                "{}: {} (proof required, challenge token \"{}\")",
+                status, err.error, token
+            ),
+            None => format!("{}: {}", status, err.error),
+        },
+        Err(_) => format!("{}: {}", status, body),
+    }
+}
+
+#[async_trait]
+impl SignalClient for SignalRestClient {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.send(&OutgoingMessage::direct(to, content))
+            .await
+            .map(|_| ())
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        let to = &message.recipient;
+        info!("🔄 Sending Signal message via signal-cli-rest-api");
+        debug!(
+            "REST send - To: {}, Content length: {} chars",
+            to,
+            message.content.len()
+        );
+
+        let styled =
+            (!message.styles.is_empty()).then(|| styled_text(&message.content, &message.styles));
+        let request = RestSendRequest {
+            number: &self.phone_number,
+            recipients: vec![rest_recipient(to)],
+            message: styled.as_deref().unwrap_or(&message.content),
+            text_mode: styled.as_ref().map(|_| "styled"),
+            quote_timestamp: message.quote.as_ref().map(|q| q.timestamp),
+            quote_author: message.quote.as_ref().map(|q| q.author.as_str()),
+            quote_message: message.quote.as_ref().map(|q| q.text.as_str()),
+        };
+        let resp = self
+            .http
+            .post(format!("{}/v2/send", self.base_url))
+            .json(&request)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let message = error_message(resp).await;
+            error!("❌ signal-cli-rest-api send failed: {}", message);
+            return Err(send_error(
+                format!("signal-cli-rest-api send failed: {}", message),
+                to,
+            ));
+        }
+
+        info!("✅ Signal message sent successfully to {}", to);
+        let sent: RestSendResponse = resp.json().await.unwrap_or_default();
+        Ok(sent.timestamp.and_then(|t| t.parse().ok()))
+    }
+
+    async fn send_typing(&self, to: &Recipient, action: TypingAction) -> anyhow::Result<()> {
+        debug!("⌨️  Sending typing {:?} to {} via REST", action, to);
+        let url = format!(
+            "{}/v1/typing-indicator/{}",
+            self.base_url, self.phone_number
+        );
+        let request = match action {
+            TypingAction::Started => self.http.put(url),
+            TypingAction::Stopped => self.http.delete(url),
+        };
+        let resp = request
+            .json(&RestTypingRequest {
+                recipient: rest_recipient(to),
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api typing indicator failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn send_read_receipt(&self, to: &str, timestamp: u64) -> anyhow::Result<()> {
+        debug!("👀 Sending read receipt to {} for {}", to, timestamp);
+        let resp = self
+            .http
+            .post(format!(
+                "{}/v1/receipts/{}",
+                self.base_url, self.phone_number
+            ))
+            .json(&RestReceiptRequest {
+                receipt_type: "read",
+                recipient: to,
+                timestamp,
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api read receipt failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn remote_delete(&self, to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        info!("🗑️  Remote-deleting message {} sent to {}", timestamp, to);
+        let resp = self
+            .http
+            .delete(format!(
+                "{}/v1/remote-delete/{}",
+                self.base_url, self.phone_number
+            ))
+            .json(&RestRemoteDeleteRequest {
+                recipient: rest_recipient(to),
+                timestamp,
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api remote delete failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn trust(&self, number: &str) -> anyhow::Result<()> {
+        info!("🔐 Trusting the new identity key of {}", number);
+        let resp = self
+            .http
+            .put(format!(
+                "{}/v1/identities/{}/trust/{}",
+                self.base_url, self.phone_number, number
+            ))
+            .json(&RestTrustRequest {
+                trust_all_known_keys: true,
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api trust failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn set_expiration(&self, to: &Recipient, seconds: u32) -> anyhow::Result<()> {
+        info!(
+            "⏳ Setting the disappearing messages timer for {} to {}s",
+            to, seconds
+        );
+        let request = match to {
+            Recipient::Direct(number) => self
+                .http
+                .put(format!(
+                    "{}/v1/contacts/{}",
+                    self.base_url, self.phone_number
+                ))
+                .json(&RestContactExpirationRequest {
+                    recipient: number,
+                    expiration_in_seconds: seconds,
+                }),
+            Recipient::Group(_) => self
+                .http
+                .put(format!(
+                    "{}/v1/groups/{}/{}",
+                    self.base_url,
+                    self.phone_number,
+                    rest_recipient(to)
+                ))
+                .json(&RestGroupExpirationRequest {
+                    expiration_time: seconds,
+                }),
+        };
+        let resp = request.send().await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api expiration update failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn update_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        info!("🪪 Updating the Signal profile to \"{}\"", profile.name);
+        // The REST API has no field for the about emoji, so it is dropped
+        let resp = self
+            .http
+            .put(format!(
+                "{}/v1/profiles/{}",
+                self.base_url, self.phone_number
+            ))
+            .json(&RestProfileRequest {
+                name: &profile.name,
+                about: profile.about.as_deref(),
+                base64_avatar: profile
+                    .avatar
+                    .as_ref()
+                    .map(|image| base64::engine::general_purpose::STANDARD.encode(image)),
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api profile update failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        challenge: &str,
+        captcha: &str,
+    ) -> anyhow::Result<()> {
+        info!("🧩 Submitting a rate limit challenge via REST");
+        let resp = self
+            .http
+            .post(format!(
+                "{}/v1/accounts/{}/rate-limit-challenge",
+                self.base_url, self.phone_number
+            ))
+            .json(&RestChallengeRequest {
+                challenge_token: challenge,
+                captcha,
+            })
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!(
+                "signal-cli-rest-api rate limit challenge failed: {}",
+                error_message(resp).await
+            );
+        }
+        Ok(())
+    }
+
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
+        debug!("🔄 Polling for Signal messages via signal-cli-rest-api");
+
+        let resp = self
+            .http
+            .get(format!("{}{}", self.base_url, self.receive_path()))
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let message = error_message(resp).await;
+            warn!("⚠️  signal-cli-rest-api receive failed: {}", message);
+            return Ok(vec![]);
+        }
+
+        let envelopes: Vec<Value> = resp.json().await?;
+        let messages: Vec<SignalMessage> = envelopes
+            .iter()
+            .filter_map(|e| parse_envelope(e, &self.phone_number))
+            .collect();
+
+        if messages.is_empty() {
+            debug!("📭 No new Signal messages");
+        } else {
+            info!("📬 Received {} Signal messages via REST", messages.len());
+        }
+        Ok(messages)
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        if !self.websocket {
+            return Ok(None);
+        }
+
+        let url = self.websocket_url();
+        info!("🔌 Connecting to signal-cli-rest-api websocket {}", url);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
+
+        let (tx, rx) = mpsc::unbounded();
+        let account = self.phone_number.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = socket.next().await {
+                let text = match frame {
+                    Ok(WsMessage::Text(text)) => text,
+                    Ok(WsMessage::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        warn!("⚠️  signal-cli-rest-api websocket error: {}", e);
+                        break;
+                    }
+                };
+                match serde_json::from_str::<Value>(&text) {
+                    Ok(value) => {
+                        if let Some(message) = parse_envelope(&value, &account) {
+                            if tx.unbounded_send(message).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(_) => debug!("⚠️  Could not parse websocket frame: {}", text),
+                }
+            }
+            warn!("⚠️  signal-cli-rest-api websocket closed");
+        });
+
+        Ok(Some(rx.boxed()))
+    }
+}
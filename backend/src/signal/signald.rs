@@ -0,0 +1,587 @@
+//! signald client over its Unix socket
+//!
+//! Connects to signald's socket directly, subscribes to the configured
+//! account and turns `IncomingMessage` events into [`SignalMessage`]s.
+//! Requests carry an id so each response can be matched up and per-recipient
+//! send failures reported by signald surface as errors. A dropped connection
+//! is re-established on the next call.
+
+use super::dispatch::{Inbox, PendingRequests, ResponseReceiver};
+use super::{
+    AvatarFile, MessageStream, OutgoingMessage, Profile, RateLimited, Recipient, SignalAddress,
+    SignalAttachment, SignalClient, SignalMessage, SignalReaction, TypingAction, UntrustedIdentity,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+pub struct SignaldClient {
+    socket_path: String,
+    phone_number: String,
+    connection: Mutex<Option<Connection>>,
+    inbox: Arc<Inbox>,
+}
+
+/// An open, subscribed signald socket
+///
+/// Requests wait on the socket they were written to, so one that closes
+/// only fails its own requests, not those sent over its replacement.
+struct Connection {
+    writer: OwnedWriteHalf,
+    pending: Arc<PendingRequests>,
+    alive: Arc<AtomicBool>,
+}
+
+#[derive(Serialize)]
+struct SignaldRequest<'a> {
+    #[serde(rename = "type")]
+    request_type: &'a str,
+    version: &'static str,
+    id: &'a str,
+    account: &'a str,
+    #[serde(flatten)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct SignaldResponse {
+    #[serde(rename = "type")]
+    response_type: String,
+    id: Option<String>,
+    data: Option<Value>,
+    error_type: Option<String>,
+    error: Option<Value>,
+}
+
+impl SignaldClient {
+    pub fn new(socket_path: String, phone_number: String) -> Self {
+        Self {
+            socket_path,
+            phone_number,
+            connection: Mutex::new(None),
+            inbox: Arc::new(Inbox::default()),
+        }
+    }
+
+    /// Send a request and wait for signald's matching response
+    async fn request(&self, request_type: &str, params: Value) -> anyhow::Result<Value> {
+        let (pending, id, rx) = {
+            let mut guard = self.connection.lock().await;
+            let connection = self.connected(&mut guard).await?;
+            let pending = connection.pending.clone();
+            match self.write_request(connection, request_type, params).await {
+                Ok((id, rx)) => (pending, id, rx),
+                Err(e) => {
+                    *guard = None;
+                    return Err(e);
+                }
+            }
+        };
+
+        pending
+            .wait(&id, rx, &format!("signald {request_type}"))
+            .await
+    }
+
+    async fn write_request(
+        &self,
+        connection: &mut Connection,
+        request_type: &str,
+        params: Value,
+    ) -> anyhow::Result<(String, ResponseReceiver)> {
+        let (id, rx) = connection.pending.register();
+        let request = SignaldRequest {
+            request_type,
+            version: "v1",
+            id: &id,
+            account: &self.phone_number,
+            params,
+        };
+        let mut line = serde_json::to_string(&request)?;
+        debug!("📤 signald request: {}", line);
+        line.push('\n');
+
+        if let Err(e) = connection.writer.write_all(line.as_bytes()).await {
+            error!("❌ Failed to write to signald socket: {}", e);
+            connection.pending.cancel(&id);
+            anyhow::bail!("signald write failed: {}", e);
+        }
+        Ok((id, rx))
+    }
+
+    /// Return the live connection, connecting and subscribing if needed
+    async fn connected<'a>(
+        &self,
+        slot: &'a mut Option<Connection>,
+    ) -> anyhow::Result<&'a mut Connection> {
+        if !slot
+            .as_ref()
+            .is_some_and(|c| c.alive.load(Ordering::SeqCst))
+        {
+            *slot = Some(self.connect().await?);
+        }
+        Ok(slot.as_mut().expect("connection was just established"))
+    }
+
+    /// Open the socket and subscribe to incoming messages for our account
+    async fn connect(&self) -> anyhow::Result<Connection> {
+        info!("🔌 Connecting to signald at {}", self.socket_path);
+        let stream = UnixStream::connect(&self.socket_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to connect to {}: {}", self.socket_path, e))?;
+        let (reader, writer) = stream.into_split();
+
+        let pending = Arc::new(PendingRequests::default());
+        let alive = Arc::new(AtomicBool::new(true));
+        tokio::spawn(read_loop(
+            reader,
+            pending.clone(),
+            self.inbox.clone(),
+            alive.clone(),
+            self.phone_number.clone(),
+        ));
+
+        let mut connection = Connection {
+            writer,
+            pending,
+            alive,
+        };
+        let (id, rx) = self
+            .write_request(&mut connection, "subscribe", json!({}))
+            .await?;
+        connection
+            .pending
+            .wait(&id, rx, "signald subscribe")
+            .await?;
+        info!(
+            "📡 Subscribed to signald messages for {}",
+            self.phone_number
+        );
+
+        Ok(connection)
+    }
+}
+
+/// Dispatch everything signald writes until the socket closes
+async fn read_loop(
+    reader: OwnedReadHalf,
+    pending: Arc<PendingRequests>,
+    inbox: Arc<Inbox>,
+    alive: Arc<AtomicBool>,
+    account: String,
+) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SignaldResponse>(&line) {
+            Ok(response) => handle_response(&pending, &inbox, &account, response),
+            Err(_) => debug!("⚠️  Could not parse signald line: {}", line),
+        }
+    }
+
+    warn!("⚠️  signald socket closed");
+    alive.store(false, Ordering::SeqCst);
+    pending.fail_all("signald connection closed");
+    inbox.close();
+}
+
+fn handle_response(
+    pending: &PendingRequests,
+    inbox: &Inbox,
+    account: &str,
+    response: SignaldResponse,
+) {
+    if response.response_type == "IncomingMessage" {
+        if let Some(message) = response
+            .data
+            .as_ref()
+            .and_then(|data| parse_incoming_message(data, account))
+        {
+            inbox.deliver(message);
+        }
+        return;
+    }
+
+    let Some(id) = response.id else {
+        debug!("🔔 Ignoring signald event: {}", response.response_type);
+        return;
+    };
+
+    let result = match (&response.error_type, &response.error) {
+        (None, None) => Ok(response.data.unwrap_or(Value::Null)),
+        (error_type, error) => {
+            let message = error
+                .as_ref()
+                .and_then(|e| e.get("message"))
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error");
+            Err(anyhow::anyhow!(
+                "signald {} failed ({}): {}",
+                response.response_type,
+                error_type.as_deref().unwrap_or("error"),
+                message
+            ))
+        }
+    };
+
+    if !pending.resolve(&id, result) {
+        debug!("⚠️  signald response for unknown id {}", id);
+    }
+}
+
+/// Convert the data of an `IncomingMessage` event into a [`SignalMessage`]
+fn parse_incoming_message(data: &Value, account: &str) -> Option<SignalMessage> {
+    let data_message = data.get("data_message")?;
+    let attachments: Vec<SignalAttachment> = data_message
+        .get("attachments")
+        .and_then(Value::as_array)
+        .map(|attachments| attachments.iter().map(parse_attachment).collect())
+        .unwrap_or_default();
+    let reaction = data_message.get("reaction").and_then(parse_reaction);
+    let remote_delete = data_message
+        .get("remoteDelete")
+        .and_then(|d| d.get("target_sent_timestamp"))
+        .and_then(Value::as_u64);
+    let body = data_message.get("body").and_then(Value::as_str);
+    let expiration_update = data_message
+        .get("isExpirationUpdate")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if body.is_none()
+        && attachments.is_empty()
+        && reaction.is_none()
+        && remote_delete.is_none()
+        && !expiration_update
+    {
+        return None;
+    }
+    let body = body.unwrap_or("");
+    let source = data.get("source")?;
+    let field = |key: &str| source.get(key).and_then(Value::as_str).map(str::to_string);
+    let source = SignalAddress {
+        uuid: field("uuid"),
+        number: field("number"),
+        ..Default::default()
+    };
+    if source.is(account) {
+        debug!("⏭️  Skipping a message our own account sent to itself");
+        return None;
+    }
+    let from = source.id();
+
+    let expires_in_seconds = data_message
+        .get("expiresInSeconds")
+        .and_then(Value::as_u64)
+        .filter(|&seconds| seconds > 0)
+        .map(|seconds| seconds as u32);
+    let group_id = data_message
+        .get("groupV2")
+        .and_then(|g| g.get("id"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let mentions = data_message
+        .get("mentions")
+        .and_then(Value::as_array)
+        .map(|mentions| {
+            mentions
+                .iter()
+                .filter_map(|m| m.get("uuid").and_then(Value::as_str))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    info!("📨 Received Signal message from {}: {}", from, body);
+    Some(SignalMessage {
+        from,
+        source,
+        to: account.to_string(),
+        content: body.to_string(),
+        group_id,
+        mentions,
+        attachments,
+        timestamp: data.get("timestamp").and_then(Value::as_u64),
+        reaction,
+        remote_delete,
+        expires_in_seconds,
+        expiration_update,
+        ..Default::default()
+    })
+}
+
+/// signald nests the target author as an address object
+fn parse_reaction(value: &Value) -> Option<SignalReaction> {
+    let author = value.get("targetAuthor");
+    Some(SignalReaction {
+        emoji: value.get("emoji")?.as_str()?.to_string(),
+        target_author: author
+            .and_then(|a| a.get("number").or_else(|| a.get("uuid")))
+            .and_then(Value::as_str)
+            .unwrap_or("unknown")
+            .to_string(),
+        target_timestamp: value.get("targetSentTimestamp")?.as_u64()?,
+        remove: value
+            .get("remove")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+    })
+}
+
+/// signald reports where it stored the file in `storedFilename`
+fn parse_attachment(value: &Value) -> SignalAttachment {
+    SignalAttachment {
+        id: value.get("id").and_then(Value::as_str).map(str::to_string),
+        content_type: value
+            .get("contentType")
+            .and_then(Value::as_str)
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        size: value.get("size").and_then(Value::as_u64),
+        filename: value
+            .get("customFilename")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        path: value
+            .get("storedFilename")
+            .and_then(Value::as_str)
+            .map(PathBuf::from),
+    }
+}
+
+/// signald addresses carry either an E.164 number or an ACI uuid
+fn recipient_address(to: &str) -> Value {
+    if to.starts_with('+') {
+        json!({ "number": to })
+    } else {
+        json!({ "uuid": to })
+    }
+}
+
+/// Turn per-recipient failures in a send response into an error
+fn check_send_results(to: &str, data: &Value) -> anyhow::Result<()> {
+    let results = data
+        .get("results")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    for result in results {
+        if result.get("success").is_some_and(|s| !s.is_null()) {
+            continue;
+        }
+        if let Some(proof) = result
+            .get("proof_required_failure")
+            .filter(|v| !v.is_null())
+        {
+            let limited = RateLimited {
+                challenge: proof
+                    .get("token")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                retry_after: proof.get("retry_after").and_then(Value::as_u64),
+            };
+            return Err(anyhow::Error::new(limited)
+                .context(format!("signald could not deliver to {to}: proof required")));
+        }
+        if result.get("identityFailure").is_some_and(|v| !v.is_null()) {
+            // In groups the failing member is only known from the result
+            let address = result.get("address");
+            let number = ["uuid", "number"]
+                .iter()
+                .find_map(|key| address?.get(key)?.as_str())
+                .unwrap_or(to);
+            let untrusted = UntrustedIdentity {
+                number: number.to_string(),
+            };
+            return Err(anyhow::Error::new(untrusted).context(format!(
+                "signald could not deliver to {to}: untrusted identity"
+            )));
+        }
+        let reason = if result.get("unregisteredFailure") == Some(&Value::Bool(true)) {
+            "recipient is not registered"
+        } else if result.get("networkFailure") == Some(&Value::Bool(true)) {
+            "network failure"
+        } else {
+            "unknown failure"
+        };
+        anyhow::bail!("signald could not deliver to {}: {}", to, reason);
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl SignalClient for SignaldClient {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.send(&OutgoingMessage::direct(to, content))
+            .await
+            .map(|_| ())
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        let to = &message.recipient;
+        info!("🔄 Sending Signal message via signald");
+        debug!(
+            "signald send - To: {}, Content length: {} chars",
+            to,
+            message.content.len()
+        );
+
+        // signald has no text styles, so `message.styles` are dropped
+        let mut params = json!({ "messageBody": message.content });
+        match to {
+            Recipient::Direct(number) => {
+                params["recipientAddress"] = recipient_address(number);
+            }
+            Recipient::Group(group_id) => {
+                params["recipientGroupId"] = json!(group_id);
+            }
+        }
+        if let Some(quote) = &message.quote {
+            params["quote"] = json!({
+                "id": quote.timestamp,
+                "author": recipient_address(&quote.author),
+                "text": quote.text,
+            });
+        }
+        let data = self.request("send", params).await?;
+        check_send_results(&to.to_string(), &data)?;
+
+        info!("✅ Signal message sent successfully to {}", to);
+        Ok(data.get("timestamp").and_then(Value::as_u64))
+    }
+
+    async fn send_typing(&self, to: &Recipient, action: TypingAction) -> anyhow::Result<()> {
+        debug!("⌨️  Sending typing {:?} to {} via signald", action, to);
+        let mut params = json!({ "typing": action == TypingAction::Started });
+        match to {
+            Recipient::Direct(number) => params["address"] = recipient_address(number),
+            Recipient::Group(group_id) => params["group"] = json!(group_id),
+        }
+        self.request("typing", params).await?;
+        Ok(())
+    }
+
+    async fn send_read_receipt(&self, to: &str, timestamp: u64) -> anyhow::Result<()> {
+        debug!("👀 Sending read receipt to {} for {}", to, timestamp);
+        let params = json!({ "to": recipient_address(to), "timestamps": [timestamp] });
+        self.request("mark_read", params).await?;
+        Ok(())
+    }
+
+    async fn remote_delete(&self, to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        info!("🗑️  Remote-deleting message {} sent to {}", timestamp, to);
+        let mut params = json!({ "timestamp": timestamp });
+        match to {
+            Recipient::Direct(number) => params["address"] = recipient_address(number),
+            Recipient::Group(group_id) => params["group"] = json!(group_id),
+        }
+        self.request("remote_delete", params).await?;
+        Ok(())
+    }
+
+    async fn trust(&self, number: &str) -> anyhow::Result<()> {
+        info!("🔐 Trusting the new identity key of {}", number);
+        // signald trusts a key by its safety number, so look up the newest
+        let address = recipient_address(number);
+        let data = self
+            .request("get_identities", json!({ "address": address }))
+            .await?;
+        let safety_number = data
+            .get("identities")
+            .and_then(Value::as_array)
+            .and_then(|identities| {
+                identities
+                    .iter()
+                    .max_by_key(|identity| identity.get("added").and_then(Value::as_u64))
+            })
+            .and_then(|identity| identity.get("safety_number"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("signald knows no identity key for {}", number))?;
+        self.request(
+            "trust",
+            json!({
+                "address": address,
+                "safety_number": safety_number,
+                "trust_level": "TRUSTED_UNVERIFIED",
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn set_expiration(&self, to: &Recipient, seconds: u32) -> anyhow::Result<()> {
+        info!(
+            "⏳ Setting the disappearing messages timer for {} to {}s",
+            to, seconds
+        );
+        let mut params = json!({ "expiration": seconds });
+        match to {
+            Recipient::Direct(number) => params["address"] = recipient_address(number),
+            Recipient::Group(group_id) => params["group"] = json!(group_id),
+        }
+        self.request("set_expiration", params).await?;
+        Ok(())
+    }
+
+    async fn update_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        info!("🪪 Updating the Signal profile to \"{}\"", profile.name);
+        let mut params = json!({ "name": profile.name });
+        if let Some(about) = &profile.about {
+            params["about"] = json!(about);
+        }
+        if let Some(emoji) = &profile.about_emoji {
+            params["emoji"] = json!(emoji);
+        }
+        // signald shares our filesystem, as its socket does
+        let avatar = match &profile.avatar {
+            Some(image) => Some(AvatarFile::write(image).await?),
+            None => None,
+        };
+        if let Some(avatar) = &avatar {
+            params["avatarFile"] = json!(avatar.path());
+        }
+        self.request("set_profile", params).await?;
+        Ok(())
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        challenge: &str,
+        captcha: &str,
+    ) -> anyhow::Result<()> {
+        info!("🧩 Submitting a rate limit challenge via signald");
+        self.request(
+            "submit_challenge",
+            json!({ "challenge": challenge, "captcha_token": captcha }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
+        self.connected(&mut *self.connection.lock().await).await?;
+
+        let messages = self.inbox.drain();
+        if messages.is_empty() {
+            debug!("📭 No new Signal messages");
+        } else {
+            info!("📬 Received {} Signal messages via signald", messages.len());
+        }
+        Ok(messages)
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        self.connected(&mut *self.connection.lock().await).await?;
+        Ok(Some(self.inbox.subscribe()))
+    }
+}
@@ -0,0 +1,152 @@
+//! An in-process stand-in for Signal, for development and tests
+//!
+//! Nothing leaves the process: incoming messages are whatever is passed to
+//! [`SimulatedSignalClient::inject`], and everything the bot sends is kept
+//! for [`SimulatedSignalClient::sent`] to read back. `SIGNAL_TRANSPORT=simulated`
+//! runs the server on it and mounts the [`crate::simulator`] endpoints, so the
+//! worker, the LLM and replies can be tried without a registered number.
+
+use super::dispatch::Inbox;
+use super::{MessageStream, OutgoingMessage, Profile, Recipient, SignalClient, SignalMessage};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::debug;
+
+/// A message the bot sent through the simulator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedMessage {
+    /// Timestamp the simulator assigned, as Signal would
+    pub timestamp: u64,
+    /// Number or uuid of the recipient, or the group id
+    pub to: String,
+    pub is_group: bool,
+    pub content: String,
+    /// Sent timestamp of the message quoted above this one
+    pub quote_timestamp: Option<u64>,
+    /// Set once the bot deleted the message for everyone
+    pub deleted: bool,
+}
+
+pub struct SimulatedSignalClient {
+    account: String,
+    inbox: Inbox,
+    sent: Mutex<Vec<SimulatedMessage>>,
+    /// Timestamps are handed out in order, starting from the current time
+    next_timestamp: AtomicU64,
+}
+
+impl SimulatedSignalClient {
+    /// Simulate Signal for the bot's account `account`
+    pub fn new(account: impl Into<String>) -> Self {
+        Self {
+            account: account.into(),
+            inbox: Inbox::default(),
+            sent: Mutex::new(Vec::new()),
+            next_timestamp: AtomicU64::new(chrono::Utc::now().timestamp_millis() as u64),
+        }
+    }
+
+    /// The number the bot is simulated to be registered with
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.next_timestamp.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Have `message` arrive as if someone sent it to the bot
+    ///
+    /// `to` defaults to the bot's account and `timestamp` to a fresh one,
+    /// which is returned so replies quoting the message can be matched up.
+    pub fn inject(&self, mut message: SignalMessage) -> u64 {
+        if message.to.is_empty() {
+            message.to = self.account.clone();
+        }
+        let timestamp = *message.timestamp.get_or_insert_with(|| self.timestamp());
+        debug!("🧪 Simulated message from {}", message.from);
+        self.inbox.deliver(message);
+        timestamp
+    }
+
+    /// Everything the bot sent so far, oldest first
+    pub fn sent(&self) -> Vec<SimulatedMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Forget everything the bot sent so far
+    pub fn clear_sent(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl SignalClient for SimulatedSignalClient {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.send(&OutgoingMessage::direct(to, content)).await?;
+        Ok(())
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        let (to, is_group) = match &message.recipient {
+            Recipient::Direct(to) => (to.clone(), false),
+            Recipient::Group(group_id) => (group_id.clone(), true),
+        };
+        let timestamp = self.timestamp();
+        debug!("🧪 Simulated send to {}", message.recipient);
+        self.sent.lock().unwrap().push(SimulatedMessage {
+            timestamp,
+            to,
+            is_group,
+            content: message.content.clone(),
+            quote_timestamp: message.quote.as_ref().map(|quote| quote.timestamp),
+            deleted: false,
+        });
+        Ok(Some(timestamp))
+    }
+
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
+        Ok(self.inbox.drain())
+    }
+
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        Ok(Some(self.inbox.subscribe()))
+    }
+
+    async fn remote_delete(&self, _to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        match sent
+            .iter_mut()
+            .find(|message| message.timestamp == timestamp)
+        {
+            Some(message) => {
+                message.deleted = true;
+                Ok(())
+            }
+            None => anyhow::bail!("no simulated message was sent at {}", timestamp),
+        }
+    }
+
+    async fn trust(&self, _number: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn set_expiration(&self, _to: &Recipient, _seconds: u32) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn update_profile(&self, _profile: &Profile) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        _challenge: &str,
+        _captcha: &str,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! A Signal client that can be replaced while the bot is running
+//!
+//! Changing the bot's number means talking to Signal through a different
+//! account, so the client in [`crate::AppState`] is this wrapper rather than a
+//! transport. Handlers and the worker keep the same `Arc`; every call goes to
+//! whichever client is current. Pushed message streams end when the client is
+//! replaced, which makes the worker subscribe again through the new one.
+
+use super::{
+    MessageStream, OutgoingMessage, Profile, Recipient, SignalClient, SignalMessage, TypingAction,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+
+/// The client calls go to, `None` until an account is registered
+type Current = Option<Arc<dyn SignalClient>>;
+
+pub struct SwitchableSignalClient {
+    current: RwLock<Current>,
+    /// Bumped every time the client is replaced
+    generation: watch::Sender<u64>,
+}
+
+impl SwitchableSignalClient {
+    /// Start with `client`, or with no account at all until one is registered
+    pub fn new(client: Option<Arc<dyn SignalClient>>) -> Self {
+        Self {
+            current: RwLock::new(client),
+            generation: watch::Sender::new(0),
+        }
+    }
+
+    /// Route all further calls to `client`
+    pub fn replace(&self, client: Arc<dyn SignalClient>) {
+        *self.current.write().unwrap() = Some(client);
+        self.generation.send_modify(|generation| *generation += 1);
+    }
+
+    /// The client calls currently go to, if an account is set up
+    pub fn current(&self) -> Option<Arc<dyn SignalClient>> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn client(&self) -> anyhow::Result<Arc<dyn SignalClient>> {
+        self.current()
+            .ok_or_else(|| anyhow::anyhow!("no Signal account has been registered yet"))
+    }
+}
+
+#[async_trait]
+impl SignalClient for SwitchableSignalClient {
+    async fn send_message(&self, to: &str, content: &str) -> anyhow::Result<()> {
+        self.client()?.send_message(to, content).await
+    }
+
+    async fn send(&self, message: &OutgoingMessage) -> anyhow::Result<Option<u64>> {
+        self.client()?.send(message).await
+    }
+
+    /// Nothing can arrive before an account is registered
+    async fn receive_messages(&self) -> anyhow::Result<Vec<SignalMessage>> {
+        match self.current() {
+            Some(client) => client.receive_messages().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Subscribe through the current client; the stream ends once it is
+    /// replaced
+    ///
+    /// Without an account the stream stays empty until one is set up, so the
+    /// worker waits for it rather than polling.
+    async fn subscribe(&self) -> anyhow::Result<Option<MessageStream>> {
+        // Watch for changes before looking at the client so none are missed
+        let mut replaced = self.generation.subscribe();
+        let stream = match self.current() {
+            Some(client) => match client.subscribe().await? {
+                Some(stream) => stream,
+                None => return Ok(None),
+            },
+            None => futures::stream::pending().boxed(),
+        };
+        let replaced = async move {
+            let _ = replaced.changed().await;
+        };
+        Ok(Some(stream.take_until(replaced).boxed()))
+    }
+
+    async fn send_typing(&self, to: &Recipient, action: TypingAction) -> anyhow::Result<()> {
+        self.client()?.send_typing(to, action).await
+    }
+
+    async fn send_read_receipt(&self, to: &str, timestamp: u64) -> anyhow::Result<()> {
+        self.client()?.send_read_receipt(to, timestamp).await
+    }
+
+    async fn remote_delete(&self, to: &Recipient, timestamp: u64) -> anyhow::Result<()> {
+        self.client()?.remote_delete(to, timestamp).await
+    }
+
+    async fn trust(&self, number: &str) -> anyhow::Result<()> {
+        self.client()?.trust(number).await
+    }
+
+    async fn set_expiration(&self, to: &Recipient, seconds: u32) -> anyhow::Result<()> {
+        self.client()?.set_expiration(to, seconds).await
+    }
+
+    async fn update_profile(&self, profile: &Profile) -> anyhow::Result<()> {
+        self.client()?.update_profile(profile).await
+    }
+
+    async fn submit_rate_limit_challenge(
+        &self,
+        challenge: &str,
+        captcha: &str,
+    ) -> anyhow::Result<()> {
+        self.client()?
+            .submit_rate_limit_challenge(challenge, captcha)
+            .await
+    }
+}
@@ -0,0 +1,142 @@
+//! Development endpoints for the Signal simulator
+//!
+//! With `SIGNAL_TRANSPORT=simulated` the bot talks to a
+//! [`SimulatedSignalClient`] instead of Signal, and these endpoints stand in
+//! for everyone else's phones: they send the bot messages from made-up
+//! senders and show what it answered. They are only mounted in that mode.
+
+use crate::error::{AppError, AppResult};
+use crate::signal::{SignalAddress, SignalMessage, SimulatedMessage, SimulatedSignalClient};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A message to the bot from a simulated sender
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InjectRequest {
+    /// Phone number, uuid or username of the sender
+    pub from: String,
+    /// Profile name the sender goes by
+    #[serde(default)]
+    pub name: Option<String>,
+    pub message: String,
+    /// Group the message is posted in, `None` for a direct message
+    #[serde(default)]
+    pub group_id: Option<String>,
+    /// Numbers and uuids @-mentioned in the message
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    /// Disappearing messages timer of the conversation
+    #[serde(default)]
+    pub expires_in_seconds: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InjectResponse {
+    /// Sent timestamp of the injected message, which replies quote
+    pub timestamp: u64,
+}
+
+/// Query parameters for reading what the bot sent
+#[derive(Debug, Deserialize)]
+pub struct SentQuery {
+    /// Only messages to this sender or group
+    pub to: Option<String>,
+}
+
+/// Routes driving `simulator`, to be merged into the app
+pub fn router(simulator: Arc<SimulatedSignalClient>) -> Router {
+    Router::new()
+        .route("/dev/signal/messages", post(inject_message))
+        .route("/dev/signal/sent", get(list_sent).delete(clear_sent))
+        .with_state(simulator)
+}
+
+/// Have a simulated sender message the bot
+///
+/// The worker picks the message up like any other; poll
+/// `GET /dev/signal/sent` for the reply.
+///
+/// # Errors
+///
+/// * `AppError::Validation` - If the sender is not a number, uuid or
+///   username, or the message is empty
+///
+/// # Example
+///
+/// ```json
+/// POST /dev/signal/messages
+/// {
+///   "from": "+15551234567",
+///   "message": "What is your view on the shipbuilding budget?"
+/// }
+///
+/// Response:
+/// {
+///   "timestamp": 1700000000000
+/// }
+/// ```
+pub async fn inject_message(
+    State(simulator): State<Arc<SimulatedSignalClient>>,
+    Json(payload): Json<InjectRequest>,
+) -> AppResult<Json<InjectResponse>> {
+    let mut source = SignalAddress::parse(&payload.from)
+        .ok_or_else(|| AppError::validation("from", "Must be a phone number, uuid or username"))?;
+    if payload.message.trim().is_empty() {
+        return Err(AppError::validation("message", "Message cannot be empty"));
+    }
+    source.name = payload.name;
+
+    let timestamp = simulator.inject(SignalMessage {
+        from: source.id(),
+        source,
+        content: payload.message,
+        group_id: payload.group_id,
+        mentions: payload.mentions,
+        expires_in_seconds: payload.expires_in_seconds,
+        ..Default::default()
+    });
+    Ok(Json(InjectResponse { timestamp }))
+}
+
+/// What the bot sent, oldest first
+///
+/// # Example
+///
+/// ```json
+/// GET /dev/signal/sent?to=%2B15551234567
+///
+/// Response:
+/// [
+///   {
+///     "timestamp": 1700000000001,
+///     "to": "+15551234567",
+///     "isGroup": false,
+///     "content": "Thank you for the question, Admiral...",
+///     "quoteTimestamp": 1700000000000,
+///     "deleted": false
+///   }
+/// ]
+/// ```
+pub async fn list_sent(
+    State(simulator): State<Arc<SimulatedSignalClient>>,
+    Query(query): Query<SentQuery>,
+) -> Json<Vec<SimulatedMessage>> {
+    let sent = simulator
+        .sent()
+        .into_iter()
+        .filter(|message| query.to.as_ref().is_none_or(|to| &message.to == to))
+        .collect();
+    Json(sent)
+}
+
+/// Forget what the bot sent, e.g. between test scenarios
+pub async fn clear_sent(State(simulator): State<Arc<SimulatedSignalClient>>) -> StatusCode {
+    simulator.clear_sent();
+    StatusCode::NO_CONTENT
+}
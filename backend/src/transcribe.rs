@@ -0,0 +1,139 @@
+//! Speech-to-text for voice notes
+//!
+//! [`Transcriber`] turns an audio file into text so the worker can answer
+//! voice notes like typed messages. Two whisper backends are provided: an
+//! HTTP server (whisper.cpp's `server` or any OpenAI-compatible
+//! `/v1/audio/transcriptions` endpoint) and a local command line tool.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::Path;
+use tokio::process::Command;
+use tracing::{debug, error, info};
+
+#[async_trait]
+pub trait Transcriber: Send + Sync + 'static {
+    /// Transcribe the audio file at `audio` into plain text
+    async fn transcribe(&self, audio: &Path) -> anyhow::Result<String>;
+}
+
+/// Calls a whisper-compatible HTTP server
+pub struct WhisperHttpTranscriber {
+    url: String,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct WhisperResponse {
+    text: String,
+}
+
+impl WhisperHttpTranscriber {
+    /// `url` is the full transcription endpoint, e.g.
+    /// `http://whisper:8080/inference` for whisper.cpp or
+    /// `http://whisper:8000/v1/audio/transcriptions` for OpenAI-compatible servers
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+/// Encode `audio` as the `file` part of a `multipart/form-data` body
+///
+/// Both whisper.cpp and OpenAI-compatible servers only need the file and a
+/// `response_format`, so this avoids pulling in a multipart implementation.
+fn multipart_body(boundary: &str, filename: &str, audio: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(audio.len() + 512);
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"response_format\"\r\n\r\n\
+             json\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(audio);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+#[async_trait]
+impl Transcriber for WhisperHttpTranscriber {
+    async fn transcribe(&self, audio: &Path) -> anyhow::Result<String> {
+        info!("🎙️  Transcribing {} via whisper server", audio.display());
+        let bytes = tokio::fs::read(audio).await?;
+        let filename = audio
+            .file_name()
+            .map(|name| name.to_string_lossy().replace('"', ""))
+            .unwrap_or_else(|| "audio".to_string());
+        let boundary = format!("whisper-{}", uuid::Uuid::new_v4().simple());
+
+        let resp = self
+            .http
+            .post(&self.url)
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(multipart_body(&boundary, &filename, &bytes))
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let error_text = resp
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            error!("❌ Whisper server error {}: {}", status, error_text);
+            anyhow::bail!("whisper server error {}: {}", status, error_text);
+        }
+
+        let data: WhisperResponse = resp.json().await?;
+        let text = data.text.trim().to_string();
+        debug!("📝 Transcript: {}", text);
+        Ok(text)
+    }
+}
+
+/// Runs a local whisper command line tool and reads the transcript from stdout
+pub struct WhisperCliTranscriber {
+    program: String,
+    args: Vec<String>,
+}
+
+impl WhisperCliTranscriber {
+    /// Run `program args... <audio file>`, e.g.
+    /// `whisper-cli -m ggml-base.en.bin -nt -np -f`
+    pub fn new(program: String, args: Vec<String>) -> Self {
+        Self { program, args }
+    }
+}
+
+#[async_trait]
+impl Transcriber for WhisperCliTranscriber {
+    async fn transcribe(&self, audio: &Path) -> anyhow::Result<String> {
+        info!("🎙️  Transcribing {} via {}", audio.display(), self.program);
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(audio)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("❌ {} failed with status: {}", self.program, output.status);
+            error!("📋 stderr: {}", stderr);
+            anyhow::bail!("{} failed: {}", self.program, stderr);
+        }
+
+        let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        debug!("📝 Transcript: {}", text);
+        Ok(text)
+    }
+}
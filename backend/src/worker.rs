@@ -1,13 +1,63 @@
-use crate::AppState;
+use crate::access::{self, Access};
+use crate::conversation::{self, Conversation, SENATOR_PERSONA};
+use crate::identities::{self, ChangeSource, IdentityStatus};
+use crate::invites::{self, Redemption};
+use crate::llm::Turn;
+use crate::signal::{
+    MessageStream, OutgoingMessage, Recipient, SignalAttachment, SignalMessage, TypingAction,
+};
+use crate::{expiry, feedback, messages, outbox, ratelimit, AppState};
+use futures::StreamExt;
 use std::time::Duration;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
+/// Polling interval for transports that cannot push messages
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pause before resubscribing after a message stream ends or fails
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(2);
+
+/// How often to resend the typing indicator, which clients drop after ~15s
+const TYPING_REFRESH: Duration = Duration::from_secs(10);
+
 pub async fn start_signal_worker(state: AppState) {
-    info!("🔄 Signal worker started - polling every 10 seconds");
+    info!("🔄 Signal worker started");
+
+    loop {
+        match state.signal.subscribe().await {
+            Ok(Some(stream)) => {
+                info!("📡 Listening for pushed Signal messages");
+                consume_message_stream(&state, stream).await;
+                warn!("⚠️  Signal message stream ended, resubscribing...");
+                sleep(RESUBSCRIBE_DELAY).await;
+            }
+            Ok(None) => {
+                info!("🔄 Transport cannot push messages - polling every 10 seconds");
+                poll_signal_messages(&state).await;
+            }
+            Err(e) => {
+                error!("❌ Failed to subscribe to Signal messages: {}", e);
+                sleep(RESUBSCRIBE_DELAY).await;
+            }
+        }
+    }
+}
 
+/// Handle pushed messages until the transport closes the stream
+async fn consume_message_stream(state: &AppState, mut stream: MessageStream) {
+    while let Some(message) = stream.next().await {
+        if handle_signal_message(state, &message).await {
+            info!("✅ Processed pushed Signal message");
+        }
+    }
+}
+
+/// Fallback polling loop for transports without push support
+async fn poll_signal_messages(state: &AppState) {
     loop {
-        match process_signal_messages(&state).await {
+        match process_signal_messages(state).await {
             Ok(processed) => {
                 if processed > 0 {
                     info!("✅ Processed {} Signal messages", processed);
@@ -22,7 +72,7 @@ pub async fn start_signal_worker(state: AppState) {
         }
 
         // Poll every 10 seconds
-        sleep(Duration::from_secs(10)).await;
+        sleep(POLL_INTERVAL).await;
     }
 }
 
@@ -32,81 +82,612 @@ async fn process_signal_messages(state: &AppState) -> anyhow::Result<usize> {
     let mut processed = 0;
 
     for message in messages {
-        info!(
-            "📨 Processing Signal message from {}: {}",
-            message.from, message.content
+        if handle_signal_message(state, &message).await {
+            processed += 1;
+        }
+    }
+
+    Ok(processed)
+}
+
+/// Generate, store and send a reply to one incoming message
+///
+/// Returns whether a reply was delivered.
+async fn handle_signal_message(state: &AppState, message: &SignalMessage) -> bool {
+    info!(
+        "📨 Processing Signal message from {}: {}",
+        message.from, message.content
+    );
+
+    // Answering ourselves could go back and forth forever
+    if state.config.is_own_message(message) {
+        debug!("⏭️  Ignoring a message from our own account");
+        return false;
+    }
+
+    // New staffers grant themselves access by texting an invite code
+    if let Some(code) = invites::parse_join(&message.content).filter(|_| message.group_id.is_none())
+    {
+        return redeem_invite(state, message, code).await;
+    }
+
+    // Blocked and unknown senders don't get answers, or a say in stored ones
+    match access::check(&state.pool, &state.config, message).await {
+        Ok(Access::Answer) => {}
+        Ok(Access::Ignore) => {
+            debug!("🚫 Ignoring message from {}", message.from);
+            return false;
+        }
+        Ok(Access::Refuse) => return refuse_unknown_sender(state, message).await,
+        Err(e) => {
+            error!(
+                "❌ Failed to check whether {} may be answered: {}",
+                message.from, e
+            );
+            return false;
+        }
+    }
+
+    // The sender's identity key changed, so their message could not be read
+    if message.untrusted_identity {
+        return handle_identity_change(state, message).await;
+    }
+
+    // The sender changed the disappearing messages timer; ours follows
+    if message.expiration_update {
+        if let Err(e) = expiry::sync_timer(&state.pool, state.signal.as_ref(), message).await {
+            warn!(
+                "⚠️  Failed to update the disappearing messages timer: {}",
+                e
+            );
+        }
+        return false;
+    }
+
+    // Reactions are feedback on an earlier answer, not something to answer
+    if let Some(reaction) = &message.reaction {
+        match feedback::record_reaction(&state.pool, &state.config, message, reaction).await {
+            Ok(true) => debug!("✅ Reaction stored as feedback"),
+            Ok(false) => {}
+            Err(e) => warn!("⚠️  Failed to store reaction: {}", e),
+        }
+        return false;
+    }
+
+    // The sender deleted an earlier message; forget what it said
+    if let Some(target) = message.remote_delete {
+        match messages::tombstone_question(&state.pool, &message.from, target).await {
+            Ok(true) => info!("🗑️  {} deleted their message {}", message.from, target),
+            Ok(false) => debug!("🤷 No stored message {} to delete", target),
+            Err(e) => warn!("⚠️  Failed to delete stored message: {}", e),
+        }
+        return false;
+    }
+
+    // Edits replace the stored question; answering them again is opt-in
+    if let Some(original) = message.edited_timestamp {
+        if !state.config.answer_edits {
+            match messages::apply_edit(
+                &state.pool,
+                &message.from,
+                original,
+                &message.content,
+                message.expires_in_seconds,
+            )
+            .await
+            {
+                Ok(Some(_)) => info!("✏️  {} edited their message {}", message.from, original),
+                Ok(None) => debug!("🤷 No stored message {} to edit", original),
+                Err(e) => warn!("⚠️  Failed to store edited message: {}", e),
+            }
+            return false;
+        }
+    }
+
+    if !state.config.should_reply(message) {
+        debug!(
+            "🔇 Not replying in group under {:?} policy",
+            state.config.group_reply_policy
         );
+        return false;
+    }
 
-        // Create Senator Budd persona prompt
-        let senator_prompt = format!(
-            "You are Senator Ted Budd of North Carolina. Respond to this message as the Senator would, \
-            keeping in mind you're helping prepare Vice Admiral Mitch Bradley for his confirmation hearing \
-            for Admiral and Commander of SOCOM. Be professional, knowledgeable about military affairs, \
-            and supportive.\n\nMessage: {}",
-            message.content
+    // Receipts and typing indicators are sends too, so they wait out a
+    // rate limit pause along with the reply
+    if sending_paused(state).await {
+        debug!("⏸️  Sending is paused, skipping the read receipt and typing indicator");
+        return reply_to_message(state, message).await;
+    }
+
+    // Let the sender know we've seen the message and are working on a reply
+    if let Some(timestamp) = message.timestamp {
+        if let Err(e) = state
+            .signal
+            .send_read_receipt(&message.from, timestamp)
+            .await
+        {
+            warn!("⚠️  Failed to send read receipt to {}: {}", message.from, e);
+        }
+    }
+    let recipient = message.reply_recipient();
+    let typing = keep_typing(state, recipient.clone()).await;
+
+    let delivered = reply_to_message(state, message).await;
+
+    typing.abort();
+    if sending_paused(state).await {
+        return delivered;
+    }
+    if let Err(e) = state
+        .signal
+        .send_typing(&recipient, TypingAction::Stopped)
+        .await
+    {
+        debug!(
+            "⚠️  Failed to clear typing indicator for {}: {}",
+            recipient, e
         );
+    }
+    delivered
+}
 
-        debug!("🤖 Generating LLM response...");
-        match state.llm.complete(&senator_prompt).await {
-            Ok(response) => {
-                info!("✅ Generated response: {}", response);
+/// Whether Signal rate limiting has paused sending
+async fn sending_paused(state: &AppState) -> bool {
+    match ratelimit::active_pause(&state.pool).await {
+        Ok(pause) => pause.is_some(),
+        Err(e) => {
+            warn!("⚠️  Failed to check for a rate limit pause: {}", e);
+            false
+        }
+    }
+}
 
-                // Store the conversation in DB
-                debug!("💾 Storing conversation in database...");
-                if let Err(e) = store_signal_conversation(state, &message, &response).await {
-                    warn!("⚠️  Failed to store Signal conversation: {}", e);
-                } else {
-                    debug!("✅ Conversation stored successfully");
-                }
+/// Redeem the invite in a `/join CODE` message and tell the sender how it went
+///
+/// Blocked senders get no reply. Returns whether a reply was delivered.
+async fn redeem_invite(state: &AppState, message: &SignalMessage, code: &str) -> bool {
+    let reply = match invites::redeem(&state.pool, message, code).await {
+        Ok(Redemption::Joined { .. }) => {
+            "Welcome aboard! You can now message the Senator's prep line."
+        }
+        Ok(Redemption::AlreadyAllowed) => "You already have access - go ahead and ask away.",
+        Ok(Redemption::Invalid) => {
+            "That invite code is not valid. It may have expired or already been used."
+        }
+        Ok(Redemption::Blocked) => {
+            debug!("🚫 Ignoring invite from blocked sender {}", message.from);
+            return false;
+        }
+        Err(e) => {
+            error!("❌ Failed to redeem invite for {}: {}", message.from, e);
+            return false;
+        }
+    };
 
-                // Send response back via Signal
-                debug!("📤 Sending response via Signal...");
-                if let Err(e) = state.signal.send_message(&message.from, &response).await {
-                    error!(
-                        "❌ Failed to send Signal response to {}: {}",
-                        message.from, e
-                    );
-                } else {
-                    info!("✅ Sent Signal response to {}", message.from);
-                    processed += 1;
+    match outbox::submit(state, &OutgoingMessage::direct(&message.from, reply), None).await {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("⚠️  Failed to answer invite from {}: {}", message.from, e);
+            false
+        }
+    }
+}
+
+/// Send the configured reply for unknown senders
+///
+/// Only new direct messages get it; reactions, edits, deletes, timer
+/// changes, unreadable messages and group chatter from unknown senders are
+/// dropped silently. Returns whether the reply was delivered.
+async fn refuse_unknown_sender(state: &AppState, message: &SignalMessage) -> bool {
+    if !message.is_new_direct_message() {
+        debug!("🚫 Ignoring message from unknown sender {}", message.from);
+        return false;
+    }
+
+    let reply = OutgoingMessage::direct(&message.from, &state.config.unknown_sender_reply);
+    match outbox::submit(state, &reply, None).await {
+        Ok(_) => {
+            info!(
+                "🚫 Told unknown sender {} they won't be answered",
+                message.from
+            );
+            true
+        }
+        Err(e) => {
+            warn!(
+                "⚠️  Failed to reply to unknown sender {}: {}",
+                message.from, e
+            );
+            false
+        }
+    }
+}
+
+/// Show a typing indicator now and keep refreshing it in the background
+///
+/// Refreshing stops if a rate limit pause begins. The returned task must be
+/// aborted once the reply has been sent.
+async fn keep_typing(state: &AppState, recipient: Recipient) -> JoinHandle<()> {
+    if let Err(e) = state
+        .signal
+        .send_typing(&recipient, TypingAction::Started)
+        .await
+    {
+        debug!(
+            "⚠️  Failed to send typing indicator to {}: {}",
+            recipient, e
+        );
+    }
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        loop {
+            sleep(TYPING_REFRESH).await;
+            if sending_paused(&state).await {
+                return;
+            }
+            if let Err(e) = state
+                .signal
+                .send_typing(&recipient, TypingAction::Started)
+                .await
+            {
+                debug!(
+                    "⚠️  Failed to refresh typing indicator for {}: {}",
+                    recipient, e
+                );
+            }
+        }
+    })
+}
+
+/// Build the reply to a message we decided to answer, then store and send it
+///
+/// Returns whether a reply was delivered.
+async fn reply_to_message(state: &AppState, message: &SignalMessage) -> bool {
+    // Mentions show up as U+FFFC placeholders in the text
+    let text = message.content.replace('\u{FFFC}', "");
+
+    // Voice notes are answered like text; anything else we cannot read gets
+    // acknowledged rather than silently dropped
+    let (transcripts, unsupported) = transcribe_voice_notes(state, &message.attachments).await;
+    let content = std::iter::once(text.trim())
+        .chain(transcripts.iter().map(String::as_str))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let acknowledgement = acknowledge_attachments(&unsupported);
+
+    let response = if content.is_empty() {
+        match acknowledgement {
+            Some(acknowledgement) => acknowledgement,
+            None => {
+                debug!("📭 Message has no text or attachments, skipping");
+                return false;
+            }
+        }
+    } else {
+        match generate_response(state, message, &content).await {
+            Some(response) => match acknowledgement {
+                Some(acknowledgement) => format!("{}\n\n{}", response, acknowledgement),
+                None => response,
+            },
+            None => return false,
+        }
+    };
+
+    // An edited question makes its earlier answers stale
+    let stale_answers = match message.edited_timestamp {
+        Some(original) => messages::answers_to_question(&state.pool, &message.from, original)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("⚠️  Failed to look up earlier answers: {}", e);
+                Vec::new()
+            }),
+        None => Vec::new(),
+    };
+
+    // Store the conversation in DB
+    debug!("💾 Storing conversation in database...");
+    let stored = match store_signal_conversation(state, message, &content, &response).await {
+        Ok(assistant_msg_id) => {
+            debug!("✅ Conversation stored successfully");
+            Some(assistant_msg_id)
+        }
+        Err(e) => {
+            warn!("⚠️  Failed to store Signal conversation: {}", e);
+            None
+        }
+    };
+
+    // The reply disappears along with the rest of the conversation
+    if let Err(e) = expiry::sync_timer(&state.pool, state.signal.as_ref(), message).await {
+        warn!("⚠️  Failed to set the disappearing messages timer: {}", e);
+    }
+
+    // Send response back via Signal, into the group if it came from one.
+    // Queued first, so the outbox keeps trying if this attempt fails.
+    debug!("📤 Sending response via Signal...");
+    let reply = message.reply(&response);
+    match outbox::submit(state, &reply, stored.as_deref()).await {
+        Ok(_) => {
+            info!("✅ Sent Signal response to {}", reply.recipient);
+            delete_stale_answers(state, &reply.recipient, stale_answers).await;
+            true
+        }
+        Err(e) => {
+            error!(
+                "❌ Failed to send Signal response to {}: {}",
+                reply.recipient, e
+            );
+            false
+        }
+    }
+}
+
+/// Record a sender's identity key change noticed on an unreadable message
+///
+/// When the new key gets trusted right away, the sender is asked to send
+/// their message again. Returns whether that request was delivered.
+async fn handle_identity_change(state: &AppState, message: &SignalMessage) -> bool {
+    let change = identities::identity_changed(
+        &state.pool,
+        state.signal.as_ref(),
+        state.config.identity_trust,
+        &message.from,
+        ChangeSource::Receive,
+    )
+    .await;
+    match change {
+        Ok(change) if change.status == IdentityStatus::Trusted => {}
+        Ok(change) => {
+            info!(
+                "🔐 Not trusting the new identity key of {} ({:?})",
+                message.from, change.status
+            );
+            return false;
+        }
+        Err(e) => {
+            error!(
+                "❌ Failed to handle identity change of {}: {}",
+                message.from, e
+            );
+            return false;
+        }
+    }
+
+    let resend = OutgoingMessage::direct(
+        &message.from,
+        "Your safety number changed, so I couldn't read your last message. \
+        Could you send it again?",
+    );
+    match outbox::submit(state, &resend, None).await {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("⚠️  Failed to ask {} to resend: {}", message.from, e);
+            false
+        }
+    }
+}
+
+/// Remove answers to the pre-edit version of a question, in Signal and the DB
+async fn delete_stale_answers(
+    state: &AppState,
+    recipient: &Recipient,
+    answers: Vec<messages::StoredAnswer>,
+) {
+    'answers: for answer in answers {
+        for timestamp in answer.sent_timestamps() {
+            if let Err(e) = state.signal.remote_delete(recipient, timestamp).await {
+                warn!("⚠️  Failed to delete stale answer {}: {}", answer.id, e);
+                continue 'answers;
+            }
+        }
+        if let Err(e) = messages::tombstone(&state.pool, &answer.id).await {
+            warn!(
+                "⚠️  Failed to mark stale answer {} deleted: {}",
+                answer.id, e
+            );
+        }
+    }
+}
+
+/// Ask the LLM to answer `content` in the Senator Budd persona, with what
+/// the sender said before
+async fn generate_response(
+    state: &AppState,
+    message: &SignalMessage,
+    content: &str,
+) -> Option<String> {
+    let conversation = Conversation {
+        sender: Some(&message.from),
+        group_id: message.group_id.as_deref(),
+    };
+    // An edit replaces its question, so the original is not history
+    let turns = conversation::turns(
+        &state.pool,
+        &state.config,
+        conversation,
+        message.edited_timestamp,
+        content,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        warn!("⚠️  Failed to load conversation history: {}", e);
+        vec![Turn::user(content)]
+    });
+
+    debug!("🤖 Generating LLM response...");
+    match state.llm.converse(SENATOR_PERSONA, &turns).await {
+        Ok(response) => {
+            info!("✅ Generated response: {}", response);
+            Some(response)
+        }
+        Err(e) => {
+            error!("❌ Failed to generate LLM response: {}", e);
+            None
+        }
+    }
+}
+
+/// Transcribe audio attachments with the configured [`Transcriber`]
+///
+/// Returns the transcripts along with every attachment that could not be
+/// turned into text.
+///
+/// [`Transcriber`]: crate::transcribe::Transcriber
+async fn transcribe_voice_notes<'a>(
+    state: &AppState,
+    attachments: &'a [SignalAttachment],
+) -> (Vec<String>, Vec<&'a SignalAttachment>) {
+    let mut transcripts = Vec::new();
+    let mut unsupported = Vec::new();
+
+    for attachment in attachments {
+        let transcript = match (&state.transcriber, &attachment.path) {
+            (Some(transcriber), Some(path)) if attachment.is_audio() => {
+                match transcriber.transcribe(path).await {
+                    Ok(text) if !text.is_empty() => Some(text),
+                    Ok(_) => {
+                        warn!("⚠️  Voice note {} transcribed to nothing", path.display());
+                        None
+                    }
+                    Err(e) => {
+                        error!("❌ Failed to transcribe {}: {}", path.display(), e);
+                        None
+                    }
                 }
             }
-            Err(e) => {
-                error!("❌ Failed to generate LLM response: {}", e);
+            _ => None,
+        };
+
+        match transcript {
+            Some(text) => {
+                info!("🎙️  Transcribed voice note: {}", text);
+                transcripts.push(text);
             }
+            None => unsupported.push(attachment),
         }
     }
 
-    Ok(processed)
+    (transcripts, unsupported)
 }
 
+/// Tell the sender which attachments could not be read
+fn acknowledge_attachments(attachments: &[&SignalAttachment]) -> Option<String> {
+    if attachments.is_empty() {
+        return None;
+    }
+
+    for attachment in attachments {
+        info!("📎 Unsupported attachment: {}", attachment.describe());
+    }
+    let described: Vec<String> = attachments.iter().map(|a| a.describe()).collect();
+    Some(format!(
+        "I received {}, but I can only read text messages and voice notes for now. \
+        Could you send the key points as text?",
+        described.join(", ")
+    ))
+}
+
+/// Store the exchange, returning the id of the stored response
 async fn store_signal_conversation(
     state: &AppState,
-    incoming: &crate::signal::SignalMessage,
+    incoming: &SignalMessage,
+    content: &str,
     response: &str,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<String> {
     use uuid::Uuid;
 
     let mut tx = state.pool.begin().await?;
+    // Stored messages disappear when the conversation's timer says so
+    let expires_in = incoming.expires_in_seconds.map(|seconds| seconds as i32);
+
+    // An edit updates the question it replaces, if we have it
+    let edited_msg_id: Option<String> = match incoming.edited_timestamp {
+        Some(original) => {
+            sqlx::query_scalar(
+                "UPDATE messages SET content = $1, edited_at = NOW(), \
+                 expires_at = NOW() + $4::INT * INTERVAL '1 second' \
+                 WHERE role = 'user' AND sender = $2 AND signal_timestamp = $3 \
+                 AND deleted_at IS NULL RETURNING id",
+            )
+            .bind(content)
+            .bind(&incoming.from)
+            .bind(original as i64)
+            .bind(expires_in)
+            .fetch_optional(&mut *tx)
+            .await?
+        }
+        None => None,
+    };
+
+    // Store incoming message, with voice notes replaced by their transcript.
+    // Edits keep the original's timestamp, which later edits and deletes use.
+    let user_msg_id = match edited_msg_id {
+        Some(id) => id,
+        None => {
+            let id = Uuid::new_v4().to_string();
+            sqlx::query(
+                "INSERT INTO messages \
+                 (id, role, content, signal_timestamp, sender, group_id, expires_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7::INT * INTERVAL '1 second')",
+            )
+            .bind(&id)
+            .bind("user")
+            .bind(content)
+            .bind(
+                incoming
+                    .edited_timestamp
+                    .or(incoming.timestamp)
+                    .map(|t| t as i64),
+            )
+            .bind(&incoming.from)
+            .bind(&incoming.group_id)
+            .bind(expires_in)
+            .execute(&mut *tx)
+            .await?;
+            id
+        }
+    };
 
-    // Store incoming message
-    let user_msg_id = Uuid::new_v4();
-    sqlx::query("INSERT INTO messages (id, role, content) VALUES ($1, $2, $3)")
-        .bind(user_msg_id.to_string())
-        .bind("user")
-        .bind(&incoming.content)
+    // Store attachment metadata against the incoming message
+    for attachment in &incoming.attachments {
+        sqlx::query(
+            "INSERT INTO attachments (id, message_id, signal_id, content_type, size, filename, path) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&user_msg_id)
+        .bind(&attachment.id)
+        .bind(&attachment.content_type)
+        .bind(attachment.size.map(|size| size as i64))
+        .bind(&attachment.filename)
+        .bind(
+            attachment
+                .path
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        )
         .execute(&mut *tx)
         .await?;
+    }
 
     // Store response
     let assistant_msg_id = Uuid::new_v4();
-    sqlx::query("INSERT INTO messages (id, role, content) VALUES ($1, $2, $3)")
-        .bind(assistant_msg_id.to_string())
-        .bind("assistant")
-        .bind(response)
-        .execute(&mut *tx)
-        .await?;
+    sqlx::query(
+        "INSERT INTO messages (id, role, content, sender, group_id, reply_to, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7::INT * INTERVAL '1 second')",
+    )
+    .bind(assistant_msg_id.to_string())
+    .bind("assistant")
+    .bind(response)
+    .bind(&incoming.to)
+    .bind(&incoming.group_id)
+    .bind(&user_msg_id)
+    .bind(expires_in)
+    .execute(&mut *tx)
+    .await?;
 
     tx.commit().await?;
-    Ok(())
+    Ok(assistant_msg_id.to_string())
 }
@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use backend::llm::LlmClient;
+
+/// Answers every prompt with a fixed Senator Budd reply
+pub struct MockLlm;
+
+#[async_trait]
+impl LlmClient for MockLlm {
+    async fn complete(&self, _prompt: &str) -> anyhow::Result<String> {
+        Ok("Mock Senator Budd response".to_string())
+    }
+}
+
+/// A phone number no earlier run has used
+pub fn unique_number() -> String {
+    format!("+1555{}", uuid::Uuid::new_v4().as_u128() % 10_000_000_000)
+}
@@ -0,0 +1,134 @@
+use backend::signal::format::{prepare, render_markdown, split, FormattedText};
+use backend::signal::{OutgoingMessage, Quote, StyleRange, TextStyle};
+
+fn range(start: usize, length: usize, style: TextStyle) -> StyleRange {
+    StyleRange {
+        start,
+        length,
+        style,
+    }
+}
+
+#[test]
+fn markdown_becomes_plain_text_with_style_ranges() {
+    let formatted = render_markdown(
+        "## Key points\n\nSOCOM is **ready**, *mostly*.\n- Budget: `$13B`\n* ~~Old plan~~",
+    );
+
+    assert_eq!(
+        formatted.text,
+        "Key points\n\nSOCOM is ready, mostly.\n• Budget: $13B\n• Old plan"
+    );
+    assert_eq!(
+        formatted.styles,
+        vec![
+            range(0, 10, TextStyle::Bold),
+            range(21, 5, TextStyle::Bold),
+            range(28, 6, TextStyle::Italic),
+            range(46, 4, TextStyle::Monospace),
+            range(53, 8, TextStyle::Strikethrough),
+        ]
+    );
+    assert_eq!(formatted.styles[1].to_string(), "21:5:BOLD");
+}
+
+#[test]
+fn markdown_leaves_ordinary_punctuation_alone() {
+    let formatted = render_markdown("Use snake_case_names, 5 * 3 * 2 and \\*literal\\* stars");
+
+    assert_eq!(
+        formatted.text,
+        "Use snake_case_names, 5 * 3 * 2 and *literal* stars"
+    );
+    assert!(formatted.styles.is_empty());
+}
+
+#[test]
+fn markdown_renders_links_code_blocks_and_nesting() {
+    let formatted =
+        render_markdown("See [the brief](https://example.com).\n```\nlet x = 1;\n```\n*a **b** c*");
+
+    assert_eq!(
+        formatted.text,
+        "See the brief (https://example.com).\nlet x = 1;\na b c"
+    );
+    assert_eq!(
+        formatted.styles,
+        vec![
+            range(37, 10, TextStyle::Monospace),
+            range(50, 1, TextStyle::Bold),
+            range(48, 5, TextStyle::Italic),
+        ]
+    );
+}
+
+#[test]
+fn style_ranges_count_utf16_code_units() {
+    // The flag is two UTF-16 surrogate pairs
+    let formatted = render_markdown("🇺🇸 **Go**");
+
+    assert_eq!(formatted.text, "🇺🇸 Go");
+    assert_eq!(formatted.styles, vec![range(5, 2, TextStyle::Bold)]);
+}
+
+#[test]
+fn long_text_splits_at_paragraph_breaks() {
+    let formatted = FormattedText {
+        text: "First paragraph here.\n\nSecond one, a bit longer.\n\nThird.".to_string(),
+        styles: vec![range(23, 6, TextStyle::Bold)],
+    };
+
+    let parts = split(&formatted, 30);
+
+    let texts: Vec<&str> = parts.iter().map(|p| p.text.as_str()).collect();
+    assert_eq!(
+        texts,
+        vec![
+            "First paragraph here.",
+            "Second one, a bit longer.",
+            "Third."
+        ]
+    );
+    assert!(parts[0].styles.is_empty());
+    assert_eq!(parts[1].styles, vec![range(0, 6, TextStyle::Bold)]);
+}
+
+#[test]
+fn split_falls_back_to_spaces_and_hard_cuts() {
+    let words = split(&render_markdown("one two three four"), 9);
+    let texts: Vec<&str> = words.iter().map(|p| p.text.as_str()).collect();
+    assert_eq!(texts, vec!["one two", "three", "four"]);
+
+    let cut = split(&render_markdown("abcdefghij"), 4);
+    let texts: Vec<&str> = cut.iter().map(|p| p.text.as_str()).collect();
+    assert_eq!(texts, vec!["abcd", "efgh", "ij"]);
+}
+
+#[test]
+fn styles_spanning_a_split_are_clipped_to_each_part() {
+    let parts = split(&render_markdown("**aaaa bbbb**"), 5);
+
+    assert_eq!(parts[0].text, "aaaa");
+    assert_eq!(parts[0].styles, vec![range(0, 4, TextStyle::Bold)]);
+    assert_eq!(parts[1].text, "bbbb");
+    assert_eq!(parts[1].styles, vec![range(0, 4, TextStyle::Bold)]);
+}
+
+#[test]
+fn prepare_quotes_only_the_first_part() {
+    let message =
+        OutgoingMessage::direct("+15550001111", "**Part one.**\n\nPart two.").with_quote(Quote {
+            timestamp: 1700000000000,
+            author: "+15550001111".to_string(),
+            text: "Question?".to_string(),
+        });
+
+    let parts = prepare(&message, 12);
+
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].content, "Part one.");
+    assert_eq!(parts[0].styles, vec![range(0, 9, TextStyle::Bold)]);
+    assert_eq!(parts[0].quote.as_ref().unwrap().timestamp, 1700000000000);
+    assert_eq!(parts[1].content, "Part two.");
+    assert!(parts[1].quote.is_none());
+}
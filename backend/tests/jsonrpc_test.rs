@@ -0,0 +1,145 @@
+use backend::signal::{SignalClient, SignalJsonRpcClient};
+use futures::StreamExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A stand-in for `signal-cli jsonRpc`: records each start in a file, pushes one
+/// incoming message, then answers `send` requests until stdin closes.
+/// `SLOW` requests are answered after two seconds, and in `exit-lingering`
+/// mode a leftover child keeps stdout open for a second after the fake exits.
+const FAKE_SIGNAL_CLI: &str = r#"#!/bin/sh
+echo started >> "$1"
+printf '%s\n' '{"jsonrpc":"2.0","method":"receive","params":{"envelope":{"source":"+15550001111","dataMessage":{"message":"Hello Senator"}},"account":"+15550000000"}}'
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":"\([^"]*\)".*/\1/p')
+  case "$line" in *SLOW*) sleep 2 ;; esac
+  case "$line" in
+    *FAIL*) printf '{"jsonrpc":"2.0","error":{"code":-1,"message":"Failed to send message"},"id":"%s"}\n' "$id" ;;
+    *) printf '{"jsonrpc":"2.0","result":{"timestamp":1700000000000},"id":"%s"}\n' "$id" ;;
+  esac
+  if [ "$2" = "exit-after-one" ]; then exit 0; fi
+  if [ "$2" = "exit-lingering" ]; then sleep 1 & exit 0; fi
+done
+"#;
+
+struct FakeSignalCli {
+    dir: PathBuf,
+}
+
+impl FakeSignalCli {
+    fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("fake-signal-cli-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("signal-cli.sh"), FAKE_SIGNAL_CLI).unwrap();
+        Self { dir }
+    }
+
+    fn client(&self, mode: &str) -> SignalJsonRpcClient {
+        let args = vec![
+            self.dir.join("signal-cli.sh").display().to_string(),
+            self.dir.join("starts").display().to_string(),
+            mode.to_string(),
+        ];
+        SignalJsonRpcClient::with_command("+15550000000".to_string(), "sh", args)
+    }
+
+    fn starts(&self) -> usize {
+        std::fs::read_to_string(self.dir.join("starts"))
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for FakeSignalCli {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[tokio::test]
+async fn jsonrpc_send_reuses_one_process() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("persistent");
+
+    client.send_message("+15550001111", "first").await.unwrap();
+    client.send_message("+15550001111", "second").await.unwrap();
+
+    assert_eq!(fake.starts(), 1, "signal-cli should only be launched once");
+}
+
+#[tokio::test]
+async fn jsonrpc_send_surfaces_error_responses() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("persistent");
+
+    let err = client
+        .send_message("+15550001111", "FAIL")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Failed to send message"));
+}
+
+#[tokio::test]
+async fn jsonrpc_receive_returns_notifications() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("persistent");
+
+    let mut received = Vec::new();
+    for _ in 0..50 {
+        received.extend(client.receive_messages().await.unwrap());
+        if !received.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].from, "+15550001111");
+    assert_eq!(received[0].to, "+15550000000");
+    assert_eq!(received[0].content, "Hello Senator");
+}
+
+#[tokio::test]
+async fn jsonrpc_subscribe_pushes_notifications() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("persistent");
+
+    let mut stream = client.subscribe().await.unwrap().expect("jsonRpc can push");
+    let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("notification should arrive")
+        .expect("stream should stay open");
+
+    assert_eq!(message.from, "+15550001111");
+    assert_eq!(message.content, "Hello Senator");
+}
+
+#[tokio::test]
+async fn jsonrpc_restarts_dead_process() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("exit-after-one");
+
+    client.send_message("+15550001111", "first").await.unwrap();
+    // Let the fake exit and the client notice before sending again
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    client.send_message("+15550001111", "second").await.unwrap();
+
+    assert_eq!(
+        fake.starts(),
+        2,
+        "signal-cli should be relaunched after exiting"
+    );
+}
+
+#[tokio::test]
+async fn jsonrpc_dead_process_only_fails_its_own_requests() {
+    let fake = FakeSignalCli::new();
+    let client = fake.client("exit-lingering");
+
+    client.send_message("+15550001111", "first").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // The first process's stdout closes while this waits on its replacement
+    client.send_message("+15550001111", "SLOW").await.unwrap();
+
+    assert_eq!(fake.starts(), 2);
+}
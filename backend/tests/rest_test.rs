@@ -0,0 +1,390 @@
+use backend::signal::{
+    OutgoingMessage, Profile, RateLimited, Recipient, SignalClient, SignalMessage,
+    SignalRestClient, StyleRange, TextStyle, TypingAction, UntrustedIdentity,
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const ACCOUNT: &str = "+15550000000";
+
+#[tokio::test]
+async fn rest_send_posts_v2_send() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .and(body_json(json!({
+            "number": ACCOUNT,
+            "recipients": ["+15550001111"],
+            "message": "Good morning"
+        })))
+        .respond_with(
+            ResponseTemplate::new(201).set_body_json(json!({"timestamp": "1700000000000"})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    client
+        .send_message("+15550001111", "Good morning")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rest_send_returns_sent_timestamp() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .respond_with(
+            ResponseTemplate::new(201).set_body_json(json!({"timestamp": "1700000000000"})),
+        )
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let timestamp = client
+        .send(&OutgoingMessage::direct("+15550001111", "Good morning"))
+        .await
+        .unwrap();
+    assert_eq!(timestamp, Some(1700000000000));
+}
+
+#[tokio::test]
+async fn rest_send_includes_quote() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .and(body_json(json!({
+            "number": ACCOUNT,
+            "recipients": ["+15550001111"],
+            "message": "On readiness: ...",
+            "quote_timestamp": 1700000000000u64,
+            "quote_author": "+15550001111",
+            "quote_message": "What about readiness?"
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let question = SignalMessage {
+        from: "+15550001111".to_string(),
+        to: ACCOUNT.to_string(),
+        content: "What about readiness?".to_string(),
+        timestamp: Some(1700000000000),
+        ..Default::default()
+    };
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    client
+        .send(&question.reply("On readiness: ..."))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rest_send_surfaces_api_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .set_body_json(json!({"error": "Failed to send message: Unregistered user"})),
+        )
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let err = client
+        .send_message("+15550001111", "hello")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Unregistered user"), "{err}");
+}
+
+#[tokio::test]
+async fn rest_receive_parses_envelopes() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("/v1/receive/{ACCOUNT}")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {
+                "envelope": {
+                    "source": "+15550001111",
+                    "timestamp": 1700000000000u64,
+                    "dataMessage": {"timestamp": 1700000000000u64, "message": "Hello Senator"}
+                },
+                "account": ACCOUNT
+            },
+            {
+                "envelope": {
+                    "source": "+15550001111",
+                    "receiptMessage": {"isDelivery": true, "timestamps": [1700000000000u64]}
+                },
+                "account": ACCOUNT
+            }
+        ])))
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let messages = client.receive_messages().await.unwrap();
+
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].from, "+15550001111");
+    assert_eq!(messages[0].content, "Hello Senator");
+}
+
+#[tokio::test]
+async fn rest_polling_client_does_not_push() {
+    let client = SignalRestClient::new("http://localhost:8080".to_string(), ACCOUNT.to_string());
+    assert!(client.subscribe().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn rest_websocket_pushes_envelopes() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let envelope = json!({
+            "envelope": {
+                "source": "+15550001111",
+                "dataMessage": {"message": "Pushed over websocket"}
+            },
+            "account": ACCOUNT
+        });
+        socket
+            .send(WsMessage::Text(envelope.to_string()))
+            .await
+            .unwrap();
+        // Keep the connection open until the client hangs up
+        while socket.next().await.is_some() {}
+    });
+
+    let client =
+        SignalRestClient::new(format!("http://{addr}"), ACCOUNT.to_string()).with_websocket();
+    let mut stream = client
+        .subscribe()
+        .await
+        .unwrap()
+        .expect("websocket mode can push");
+    let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("envelope should arrive")
+        .expect("stream should stay open");
+
+    assert_eq!(message.from, "+15550001111");
+    assert_eq!(message.content, "Pushed over websocket");
+}
+
+#[tokio::test]
+async fn rest_sends_typing_indicators_and_read_receipts() {
+    let server = MockServer::start().await;
+    let typing_path = format!("/v1/typing-indicator/{ACCOUNT}");
+    Mock::given(method("PUT"))
+        .and(path(typing_path.as_str()))
+        .and(body_json(json!({"recipient": "+15550001111"})))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path(typing_path.as_str()))
+        .and(body_json(json!({"recipient": "+15550001111"})))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/v1/receipts/{ACCOUNT}")))
+        .and(body_json(json!({
+            "receipt_type": "read",
+            "recipient": "+15550001111",
+            "timestamp": 1700000000000u64
+        })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let sender = Recipient::Direct("+15550001111".to_string());
+    client
+        .send_read_receipt("+15550001111", 1700000000000)
+        .await
+        .unwrap();
+    client
+        .send_typing(&sender, TypingAction::Started)
+        .await
+        .unwrap();
+    client
+        .send_typing(&sender, TypingAction::Stopped)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rest_send_writes_styles_as_styled_markup() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .and(body_json(json!({
+            "number": ACCOUNT,
+            "recipients": ["+15550001111"],
+            "message": "SOCOM is **ready**, *mostly*.",
+            "text_mode": "styled"
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut message = OutgoingMessage::direct("+15550001111", "SOCOM is ready, mostly.");
+    message.styles = vec![
+        StyleRange {
+            start: 9,
+            length: 5,
+            style: TextStyle::Bold,
+        },
+        StyleRange {
+            start: 16,
+            length: 6,
+            style: TextStyle::Italic,
+        },
+    ];
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    client.send(&message).await.unwrap();
+}
+
+#[tokio::test]
+async fn rest_send_reports_untrusted_identities_and_trusts_them() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "Failed to send message: Untrusted Identity for \"+15550001111\""
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path(format!("/v1/identities/{ACCOUNT}/trust/+15550001111")))
+        .and(body_json(json!({"trust_all_known_keys": true})))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let err = client
+        .send(&OutgoingMessage::new(
+            Recipient::Group("Z3JvdXA=".to_string()),
+            "Good morning",
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<UntrustedIdentity>(),
+        Some(&UntrustedIdentity {
+            number: "+15550001111".to_string()
+        })
+    );
+    assert!(err.to_string().contains("Untrusted Identity"), "{err}");
+
+    client.trust("+15550001111").await.unwrap();
+}
+
+#[tokio::test]
+async fn rest_update_profile_sends_base64_avatar() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path(format!("/v1/profiles/{ACCOUNT}")))
+        .and(body_json(json!({
+            "name": "Senator Budd (Prep Bot)",
+            "about": "Hearing prep",
+            "base64_avatar": "iVBORw==",
+        })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    client
+        .update_profile(&Profile {
+            name: "Senator Budd (Prep Bot)".to_string(),
+            about: Some("Hearing prep".to_string()),
+            about_emoji: Some("🏛️".to_string()),
+            avatar: Some(b"\x89PNG".to_vec()),
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn rest_send_reports_rate_limits_and_submits_captchas() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "Failed to send message: CAPTCHA proof required for sending to \"+15550001111\", \
+                      available options \"RECAPTCHA, PUSH_CHALLENGE\" with challenge token \"abc-123\", \
+                      or wait \"86400\" seconds."
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/v2/send"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "error": "Failed to send message due to rate limiting",
+            "challenge_tokens": ["def-456"]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/v1/accounts/{ACCOUNT}/rate-limit-challenge")))
+        .and(body_json(json!({
+            "challenge_token": "abc-123",
+            "captcha": "signalcaptcha://solved"
+        })))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let client = SignalRestClient::new(server.uri(), ACCOUNT.to_string());
+    let err = client
+        .send_message("+15550001111", "Good morning")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<RateLimited>(),
+        Some(&RateLimited {
+            challenge: Some("abc-123".to_string()),
+            retry_after: Some(86400),
+        })
+    );
+    let err = client
+        .send_message("+15550001111", "Good morning")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<RateLimited>()
+            .unwrap()
+            .challenge
+            .as_deref(),
+        Some("def-456")
+    );
+
+    client
+        .submit_rate_limit_challenge("abc-123", "signalcaptcha://solved")
+        .await
+        .unwrap();
+}
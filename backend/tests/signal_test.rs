@@ -1,5 +1,7 @@
 use async_trait::async_trait;
-use backend::signal::{SignalClient, SignalMessage};
+use backend::signal::{
+    parse_envelope, EnvelopeKind, Quote, Recipient, SignalAddress, SignalClient, SignalMessage,
+};
 
 struct MockSignalClient {
     should_fail: bool,
@@ -25,6 +27,7 @@ impl SignalClient for MockSignalClient {
             from: "+1234567890".to_string(),
             to: "+0987654321".to_string(),
             content: "Test message".to_string(),
+            ..Default::default()
         }])
     }
 }
@@ -50,3 +53,342 @@ async fn signal_client_receive_messages() {
     assert_eq!(messages.len(), 1);
     assert_eq!(messages[0].content, "Test message");
 }
+
+#[test]
+fn parse_envelope_reads_group_and_mentions() {
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "timestamp": 1700000000000u64,
+            "dataMessage": {
+                "message": "\u{FFFC} what's on the agenda?",
+                "groupInfo": {"groupId": "kR3xZq8sTg6bWm0aQyV1bA==", "type": "DELIVER"},
+                "mentions": [{
+                    "name": "+15550000000",
+                    "number": "+15550000000",
+                    "uuid": "7a1e4d52-0000-4000-8000-000000000000",
+                    "start": 0,
+                    "length": 1
+                }]
+            }
+        },
+        "account": "+15550000000"
+    });
+
+    let message = parse_envelope(&line, "+15550000000").unwrap();
+    assert_eq!(
+        message.group_id.as_deref(),
+        Some("kR3xZq8sTg6bWm0aQyV1bA==")
+    );
+    assert!(message.mentions_any(&["+15550000000"]));
+    assert!(message.mentions_any(&["7a1e4d52-0000-4000-8000-000000000000"]));
+    assert_eq!(
+        message.reply("On it").recipient,
+        Recipient::Group("kR3xZq8sTg6bWm0aQyV1bA==".to_string())
+    );
+}
+
+#[test]
+fn parse_envelope_direct_message_replies_to_sender() {
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "dataMessage": {"message": "Hello"}
+        }
+    });
+
+    let message = parse_envelope(&line, "+15550000000").unwrap();
+    assert_eq!(message.group_id, None);
+    assert_eq!(
+        message.reply("Hi").recipient,
+        Recipient::Direct("+15550001111".to_string())
+    );
+}
+
+#[test]
+fn parse_envelope_keeps_attachment_only_messages() {
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "dataMessage": {
+                "timestamp": 1700000000000u64,
+                "attachments": [{
+                    "contentType": "image/jpeg",
+                    "filename": "hearing-room.jpg",
+                    "id": "Xb3kQ9vLr2TmPz8wNc1d.jpg",
+                    "size": 183274,
+                    "width": 1600,
+                    "height": 1200
+                }]
+            }
+        }
+    });
+
+    let message = parse_envelope(&line, "+15550000000").expect("attachment-only message");
+    assert_eq!(message.content, "");
+    assert_eq!(message.attachments.len(), 1);
+
+    let attachment = &message.attachments[0];
+    assert_eq!(attachment.content_type, "image/jpeg");
+    assert_eq!(attachment.size, Some(183274));
+    assert_eq!(attachment.filename.as_deref(), Some("hearing-room.jpg"));
+    assert!(attachment
+        .path
+        .as_ref()
+        .unwrap()
+        .ends_with("signal-cli/attachments/Xb3kQ9vLr2TmPz8wNc1d.jpg"));
+}
+
+#[test]
+fn parse_envelope_reads_reactions() {
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "timestamp": 1700000005000u64,
+            "dataMessage": {
+                "timestamp": 1700000005000u64,
+                "reaction": {
+                    "emoji": "👎",
+                    "targetAuthor": "+15550000000",
+                    "targetAuthorNumber": "+15550000000",
+                    "targetAuthorUuid": "7a1e4d52-0000-4000-8000-000000000000",
+                    "targetSentTimestamp": 1700000000000u64,
+                    "isRemove": false
+                }
+            }
+        }
+    });
+
+    let message = parse_envelope(&line, "+15550000000").expect("reaction message");
+    let reaction = message.reaction.expect("reaction should be parsed");
+    assert_eq!(reaction.emoji, "👎");
+    assert_eq!(reaction.target_author, "+15550000000");
+    assert_eq!(reaction.target_timestamp, 1700000000000);
+    assert!(!reaction.remove);
+}
+
+#[test]
+fn reply_quotes_the_original_message() {
+    let question = SignalMessage {
+        from: "+15550001111".to_string(),
+        to: "+15550000000".to_string(),
+        content: "What about the NDAA?".to_string(),
+        timestamp: Some(1700000000000),
+        ..Default::default()
+    };
+
+    let reply = question.reply("Here's how I'd frame it...");
+    assert_eq!(
+        reply.quote,
+        Some(Quote {
+            timestamp: 1700000000000,
+            author: "+15550001111".to_string(),
+            text: "What about the NDAA?".to_string(),
+        })
+    );
+
+    // Without a timestamp there is nothing to quote
+    let untimed = SignalMessage {
+        timestamp: None,
+        ..question
+    };
+    assert_eq!(untimed.reply("Hi").quote, None);
+}
+
+#[test]
+fn parse_envelope_reads_edits_and_remote_deletes() {
+    let edit = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "timestamp": 1700000009000u64,
+            "editMessage": {
+                "targetSentTimestamp": 1700000000000u64,
+                "dataMessage": {"timestamp": 1700000009000u64, "message": "What about the FY26 budget?"}
+            }
+        }
+    });
+    let message = parse_envelope(&edit, "+15550000000").expect("edit message");
+    assert_eq!(message.content, "What about the FY26 budget?");
+    assert_eq!(message.edited_timestamp, Some(1700000000000));
+    assert_eq!(message.reply("...").quote.unwrap().timestamp, 1700000000000);
+
+    let delete = serde_json::json!({
+        "envelope": {
+            "source": "+15550001111",
+            "dataMessage": {
+                "timestamp": 1700000010000u64,
+                "remoteDelete": {"timestamp": 1700000000000u64}
+            }
+        }
+    });
+    let message = parse_envelope(&delete, "+15550000000").expect("remote delete");
+    assert_eq!(message.remote_delete, Some(1700000000000));
+}
+
+#[test]
+fn parse_envelope_flags_untrusted_identities() {
+    let envelope = serde_json::json!({
+        "envelope": {"source": "+15550001111", "timestamp": 1700000011000u64},
+        "account": "+15550000000",
+        "exception": {
+            "message": "org.whispersystems.libsignal.UntrustedIdentityException",
+            "type": "UntrustedIdentityException"
+        }
+    });
+    let message = parse_envelope(&envelope, "+15550000000").expect("untrusted identity");
+    assert!(message.untrusted_identity);
+    assert_eq!(message.from, "+15550001111");
+    assert!(message.content.is_empty());
+}
+
+#[test]
+fn parse_envelope_keys_senders_by_uuid() {
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "3f9c2a10-0000-4000-8000-000000000002",
+            "sourceNumber": "+15550001111",
+            "sourceUuid": "3f9c2a10-0000-4000-8000-000000000002",
+            "sourceName": "Mitch Bradley",
+            "timestamp": 1700000012000u64,
+            "dataMessage": {"message": "Morning, Senator"}
+        },
+        "account": "+15550000000"
+    });
+    let message = parse_envelope(&line, "+15550000000").unwrap();
+    assert_eq!(message.from, "3f9c2a10-0000-4000-8000-000000000002");
+    assert_eq!(message.source.number.as_deref(), Some("+15550001111"));
+    assert_eq!(message.source.name.as_deref(), Some("Mitch Bradley"));
+    assert_eq!(
+        message.sender_ids(),
+        vec!["3f9c2a10-0000-4000-8000-000000000002", "+15550001111"]
+    );
+
+    // With phone number privacy on, only the uuid is reported
+    let line = serde_json::json!({
+        "envelope": {
+            "source": "3f9c2a10-0000-4000-8000-000000000002",
+            "sourceNumber": null,
+            "timestamp": 1700000013000u64,
+            "dataMessage": {"message": "Still there?"}
+        }
+    });
+    let message = parse_envelope(&line, "+15550000000").unwrap();
+    assert_eq!(message.from, "3f9c2a10-0000-4000-8000-000000000002");
+    assert_eq!(message.source.number, None);
+    assert_eq!(
+        message.reply_recipient(),
+        Recipient::Direct("3f9c2a10-0000-4000-8000-000000000002".to_string())
+    );
+}
+
+#[test]
+fn signal_addresses_parse_numbers_uuids_and_usernames() {
+    let number = SignalAddress::parse("+15550001111").unwrap();
+    assert_eq!(number.id(), "+15550001111");
+    let uuid = SignalAddress::parse("3F9C2A10-0000-4000-8000-000000000002").unwrap();
+    assert_eq!(uuid.id(), "3f9c2a10-0000-4000-8000-000000000002");
+    let username = SignalAddress::parse("budd_prep.42").unwrap();
+    assert_eq!(username.username.as_deref(), Some("budd_prep.42"));
+    assert_eq!(username.id(), "u:budd_prep.42");
+    assert_eq!(SignalAddress::parse("u:budd_prep.42"), Some(username));
+
+    for invalid in ["+1555", "15550001111", "budd", "budd.4", "9budd.42", ""] {
+        assert_eq!(SignalAddress::parse(invalid), None, "{invalid}");
+    }
+}
+
+#[test]
+fn parse_envelope_skips_everything_but_data_from_others() {
+    let envelope = |content: serde_json::Value| {
+        let mut envelope = serde_json::json!({
+            "source": "+15550001111",
+            "sourceNumber": "+15550001111",
+            "timestamp": 1700000014000u64
+        });
+        envelope
+            .as_object_mut()
+            .unwrap()
+            .extend(content.as_object().unwrap().clone());
+        serde_json::json!({ "envelope": envelope, "account": "+15550000000" })
+    };
+
+    let cases = [
+        (
+            serde_json::json!({"syncMessage": {"sentMessage": {
+                "destination": "+15550001111",
+                "message": "Sent from the phone"
+            }}}),
+            EnvelopeKind::Sync,
+        ),
+        (
+            serde_json::json!({"receiptMessage": {"isRead": true, "timestamps": [1]}}),
+            EnvelopeKind::Receipt,
+        ),
+        (
+            serde_json::json!({"typingMessage": {"action": "STARTED"}}),
+            EnvelopeKind::Typing,
+        ),
+        (
+            serde_json::json!({"callMessage": {"offerMessage": {"id": 1}}}),
+            EnvelopeKind::Call,
+        ),
+        (
+            serde_json::json!({"storyMessage": {"textAttachment": {"text": "Hi"}}}),
+            EnvelopeKind::Story,
+        ),
+        (serde_json::json!({}), EnvelopeKind::Other),
+    ];
+    for (content, kind) in cases {
+        let line = envelope(content);
+        assert_eq!(EnvelopeKind::of(&line["envelope"]), kind);
+        assert!(parse_envelope(&line, "+15550000000").is_none(), "{kind:?}");
+    }
+
+    let line = envelope(serde_json::json!({"dataMessage": {"message": "Hello"}}));
+    assert_eq!(EnvelopeKind::of(&line["envelope"]), EnvelopeKind::Data);
+    assert!(parse_envelope(&line, "+15550000000").is_some());
+    // The same message is a note to self when the bot's account sent it
+    assert!(parse_envelope(&line, "+15550001111").is_none());
+
+    // Any of the sender's ids counts, so a uuid-only envelope is caught too
+    let uuid = "c0ffee00-0000-4000-8000-000000000001";
+    let line = serde_json::json!({
+        "envelope": {
+            "sourceUuid": uuid,
+            "timestamp": 1700000014000u64,
+            "dataMessage": {"message": "Hello"}
+        },
+        "account": uuid
+    });
+    assert!(parse_envelope(&line, "+15550000000").is_some());
+    assert!(parse_envelope(&line, uuid).is_none());
+}
+
+#[test]
+fn parse_envelope_reads_disappearing_message_timers() {
+    let line = |expires: u32| {
+        serde_json::json!({"envelope": {
+            "source": "+15550001111",
+            "timestamp": 1700000015000u64,
+            "dataMessage": {"message": "Hello", "expiresInSeconds": expires}
+        }})
+    };
+
+    let message = parse_envelope(&line(3600), "+15550000000").unwrap();
+    assert_eq!(message.expires_in_seconds, Some(3600));
+    // 0 means the timer is off
+    let message = parse_envelope(&line(0), "+15550000000").unwrap();
+    assert_eq!(message.expires_in_seconds, None);
+    assert!(!message.expiration_update);
+
+    // Changing the timer sends a data message with nothing else in it
+    let line = serde_json::json!({"envelope": {
+        "source": "+15550001111",
+        "timestamp": 1700000016000u64,
+        "dataMessage": {"expiresInSeconds": 86400, "isExpirationUpdate": true}
+    }});
+    let message = parse_envelope(&line, "+15550000000").unwrap();
+    assert!(message.expiration_update);
+    assert_eq!(message.expires_in_seconds, Some(86400));
+    assert_eq!(message.content, "");
+}
@@ -0,0 +1,246 @@
+use backend::signal::{Recipient, SignalClient, SignaldClient, TypingAction, UntrustedIdentity};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+const ACCOUNT: &str = "+15550000000";
+
+/// A minimal signald: acknowledges `subscribe` and then pushes one incoming
+/// message, and answers `send` with success or a per-recipient failure.
+struct FakeSignald {
+    socket_path: PathBuf,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl FakeSignald {
+    fn start() -> Self {
+        let socket_path =
+            std::env::temp_dir().join(format!("fake-signald-{}.sock", uuid::Uuid::new_v4()));
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, seen.clone()));
+            }
+        });
+
+        Self {
+            socket_path,
+            requests,
+        }
+    }
+
+    fn client(&self) -> SignaldClient {
+        SignaldClient::new(self.socket_path.display().to_string(), ACCOUNT.to_string())
+    }
+
+    fn requests_of_type(&self, request_type: &str) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r["type"] == request_type)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for FakeSignald {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+async fn serve(stream: UnixStream, seen: Arc<Mutex<Vec<Value>>>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let version = json!({"type": "version", "data": {"name": "signald", "version": "0.23.2"}});
+    writer
+        .write_all(format!("{version}\n").as_bytes())
+        .await
+        .unwrap();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let request: Value = serde_json::from_str(&line).unwrap();
+        seen.lock().unwrap().push(request.clone());
+        let id = request["id"].clone();
+
+        let mut replies = Vec::new();
+        match request["type"].as_str() {
+            Some("subscribe") => {
+                replies.push(json!({"type": "subscribe", "id": id}));
+                replies.push(json!({
+                    "type": "IncomingMessage",
+                    "data": {
+                        "account": ACCOUNT,
+                        "source": {"number": "+15550001111", "uuid": "0d5c4b6e-0000-4000-8000-000000000001"},
+                        "timestamp": 1700000000000u64,
+                        "data_message": {"timestamp": 1700000000000u64, "body": "Hello Senator"}
+                    }
+                }));
+            }
+            Some("get_identities") => {
+                replies.push(json!({
+                    "type": "get_identities",
+                    "id": id,
+                    "data": {"identities": [
+                        {"trust_level": "TRUSTED_UNVERIFIED", "added": 1600000000000u64, "safety_number": "11111"},
+                        {"trust_level": "UNTRUSTED", "added": 1700000000000u64, "safety_number": "22222"}
+                    ]}
+                }));
+            }
+            Some(request_type @ ("typing" | "mark_read" | "trust")) => {
+                replies.push(json!({"type": request_type, "id": id}));
+            }
+            Some("send") if request["messageBody"] == "FAIL" => {
+                replies.push(json!({
+                    "type": "send",
+                    "id": id,
+                    "data": {"results": [{
+                        "address": request["recipientAddress"],
+                        "identityFailure": "05abcdef"
+                    }]}
+                }));
+            }
+            Some("send") => {
+                replies.push(json!({
+                    "type": "send",
+                    "id": id,
+                    "data": {"results": [{
+                        "address": request["recipientAddress"],
+                        "success": {"unidentified": false, "duration": 120}
+                    }], "timestamp": 1700000000001u64}
+                }));
+            }
+            _ => {
+                replies.push(json!({
+                    "type": request["type"],
+                    "id": id,
+                    "error_type": "UnknownRequestType",
+                    "error": {"message": "unknown request"}
+                }));
+            }
+        }
+
+        for reply in replies {
+            writer
+                .write_all(format!("{reply}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn signald_subscribes_and_receives_incoming_messages() {
+    let fake = FakeSignald::start();
+    let client = fake.client();
+
+    let mut stream = client.subscribe().await.unwrap().expect("signald can push");
+    let message = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .expect("incoming message should arrive")
+        .expect("stream should stay open");
+
+    // Conversations are keyed by the stable uuid; the number is kept alongside
+    assert_eq!(message.from, "0d5c4b6e-0000-4000-8000-000000000001");
+    assert_eq!(message.source.number.as_deref(), Some("+15550001111"));
+    assert_eq!(message.to, ACCOUNT);
+    assert_eq!(message.content, "Hello Senator");
+
+    let subscribes = fake.requests_of_type("subscribe");
+    assert_eq!(subscribes.len(), 1);
+    assert_eq!(subscribes[0]["account"], ACCOUNT);
+}
+
+#[tokio::test]
+async fn signald_send_succeeds_over_socket() {
+    let fake = FakeSignald::start();
+    let client = fake.client();
+
+    client
+        .send_message("+15550001111", "Good morning")
+        .await
+        .unwrap();
+
+    let sends = fake.requests_of_type("send");
+    assert_eq!(sends.len(), 1);
+    assert_eq!(sends[0]["recipientAddress"]["number"], "+15550001111");
+    assert_eq!(sends[0]["messageBody"], "Good morning");
+}
+
+#[tokio::test]
+async fn signald_send_surfaces_recipient_failures() {
+    let fake = FakeSignald::start();
+    let client = fake.client();
+
+    let err = client
+        .send_message("+15550001111", "FAIL")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("untrusted identity"), "{err}");
+}
+
+#[tokio::test]
+async fn signald_reports_untrusted_identities_and_trusts_the_new_key() {
+    let fake = FakeSignald::start();
+    let client = fake.client();
+
+    let err = client
+        .send_message("+15550001111", "FAIL")
+        .await
+        .unwrap_err();
+    let untrusted = err
+        .downcast_ref::<UntrustedIdentity>()
+        .expect("identity failures should be UntrustedIdentity");
+    assert_eq!(untrusted.number, "+15550001111");
+
+    client.trust(&untrusted.number).await.unwrap();
+    let trusts = fake.requests_of_type("trust");
+    assert_eq!(trusts.len(), 1);
+    assert_eq!(trusts[0]["address"]["number"], "+15550001111");
+    assert_eq!(trusts[0]["safety_number"], "22222");
+    assert_eq!(trusts[0]["trust_level"], "TRUSTED_UNVERIFIED");
+}
+
+#[tokio::test]
+async fn signald_reports_unreachable_socket() {
+    let client = SignaldClient::new("/nonexistent/signald.sock".to_string(), ACCOUNT.to_string());
+
+    assert!(client.send_message("+15550001111", "hello").await.is_err());
+}
+
+#[tokio::test]
+async fn signald_sends_typing_and_mark_read() {
+    let fake = FakeSignald::start();
+    let client = fake.client();
+
+    client
+        .send_read_receipt("+15550001111", 1700000000000)
+        .await
+        .unwrap();
+    client
+        .send_typing(
+            &Recipient::Direct("+15550001111".to_string()),
+            TypingAction::Started,
+        )
+        .await
+        .unwrap();
+
+    let reads = fake.requests_of_type("mark_read");
+    assert_eq!(reads.len(), 1);
+    assert_eq!(reads[0]["to"]["number"], "+15550001111");
+    assert_eq!(reads[0]["timestamps"], json!([1700000000000u64]));
+
+    let typing = fake.requests_of_type("typing");
+    assert_eq!(typing.len(), 1);
+    assert_eq!(typing[0]["address"]["number"], "+15550001111");
+    assert_eq!(typing[0]["typing"], true);
+}
@@ -0,0 +1,69 @@
+use backend::transcribe::{Transcriber, WhisperCliTranscriber, WhisperHttpTranscriber};
+use serde_json::json;
+use std::path::PathBuf;
+use wiremock::matchers::{header_regex, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Write `contents` to a fresh file standing in for a downloaded voice note
+fn voice_note(contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("voice-note-{}.aac", uuid::Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn whisper_http_uploads_audio_and_returns_text() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/inference"))
+        .and(header_regex(
+            "content-type",
+            "^multipart/form-data; boundary=",
+        ))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"text": " Senator, my answer on readiness is... \n"})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let audio = voice_note(b"fake aac bytes");
+    let transcriber = WhisperHttpTranscriber::new(format!("{}/inference", server.uri()));
+    let text = transcriber.transcribe(&audio).await.unwrap();
+    std::fs::remove_file(&audio).unwrap();
+
+    assert_eq!(text, "Senator, my answer on readiness is...");
+
+    let requests = server.received_requests().await.unwrap();
+    let body = String::from_utf8_lossy(&requests[0].body);
+    assert!(body.contains("name=\"file\""), "{body}");
+    assert!(body.contains("fake aac bytes"), "{body}");
+}
+
+#[tokio::test]
+async fn whisper_http_surfaces_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("model not loaded"))
+        .mount(&server)
+        .await;
+
+    let audio = voice_note(b"fake aac bytes");
+    let transcriber = WhisperHttpTranscriber::new(format!("{}/inference", server.uri()));
+    let err = transcriber.transcribe(&audio).await.unwrap_err();
+    std::fs::remove_file(&audio).unwrap();
+
+    assert!(err.to_string().contains("model not loaded"), "{err}");
+}
+
+#[tokio::test]
+async fn whisper_cli_reads_transcript_from_stdout() {
+    // `cat` stands in for whisper: it prints the "audio" file as the transcript
+    let audio = voice_note(b"  What is SOCOM's top priority?\n");
+    let transcriber = WhisperCliTranscriber::new("cat".to_string(), vec![]);
+    let text = transcriber.transcribe(&audio).await.unwrap();
+    std::fs::remove_file(&audio).unwrap();
+
+    assert_eq!(text, "What is SOCOM's top priority?");
+}